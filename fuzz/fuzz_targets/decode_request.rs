@@ -0,0 +1,33 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Feeds arbitrary packets to `decode_request`, as the agent does with
+//! whatever arrives on its socket.
+
+#![no_main]
+
+use gimlet_inspector_protocol::{
+    decode_request, encode_request, HasTrailer, REQUEST_MAX_SIZE,
+    REQUEST_TRAILER,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok((request, trailer)) = decode_request(data) else {
+        return;
+    };
+
+    // Anything accepted must fit in the agent's receive buffer, with the
+    // trailer at the very end of the packet and within its limit.
+    assert!(data.len() <= REQUEST_MAX_SIZE);
+    assert!(trailer.len() <= request.max_trailer());
+    assert!(request.max_trailer() <= REQUEST_TRAILER);
+    assert_eq!(trailer.as_ptr_range().end, data.as_ptr_range().end);
+
+    // hubpack encodings are canonical, so re-encoding must reproduce the
+    // packet exactly.
+    let mut buf = [0; REQUEST_MAX_SIZE];
+    let len = encode_request(&request, trailer, &mut buf).unwrap();
+    assert_eq!(&buf[..len], data);
+});
@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Feeds arbitrary packets to the response decoders, as a client does with
+//! whatever arrives on its socket.
+
+#![no_main]
+
+use core::fmt::Debug;
+
+use gimlet_inspector_protocol::{
+    decode_response, decode_response_v1, encode_response, encode_response_v1,
+    CapabilitiesResponseV0, FpgaStatusResponseV0, HostPowerStateResponseV0,
+    IdentityResponseV0, PostCodesResponseV0, PowerRailsResponseV0, Response,
+    RingbufResponseV0, SequencerRegistersResponseV0, TasksResponseV0,
+    TemperaturesResponseV0, ANY_RESPONSE_V0_MAX_SIZE, ANY_RESPONSE_V1_MAX_SIZE,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    check::<SequencerRegistersResponseV0>(data);
+    check::<CapabilitiesResponseV0>(data);
+    check::<TemperaturesResponseV0>(data);
+    check::<PowerRailsResponseV0>(data);
+    check::<HostPowerStateResponseV0>(data);
+    check::<TasksResponseV0>(data);
+    check::<IdentityResponseV0>(data);
+    check::<FpgaStatusResponseV0>(data);
+    check::<PostCodesResponseV0>(data);
+    check::<RingbufResponseV0>(data);
+});
+
+fn check<T: Response + PartialEq + Debug>(data: &[u8]) {
+    let mut buf = [0; ANY_RESPONSE_V1_MAX_SIZE];
+
+    if let Ok((response, trailer)) = decode_response::<T>(data) {
+        assert!(data.len() <= ANY_RESPONSE_V0_MAX_SIZE);
+        assert!(trailer.len() <= response.max_trailer());
+        assert!(response.max_trailer() <= T::MAX_TRAILER);
+        assert_eq!(trailer.as_ptr_range().end, data.as_ptr_range().end);
+        let len = encode_response(&response, trailer, &mut buf).unwrap();
+        assert_eq!(&buf[..len], data);
+    }
+
+    // Expect whatever ID the packet carries, so that we get past the ID check
+    // and into the body.
+    let id = data
+        .get(1..5)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .unwrap_or(0);
+    if let Ok((response, trailer)) = decode_response_v1::<T>(id, data) {
+        assert!(data.len() <= ANY_RESPONSE_V1_MAX_SIZE);
+        assert!(trailer.len() <= response.max_trailer());
+        assert!(response.max_trailer() <= T::MAX_TRAILER);
+        assert_eq!(trailer.as_ptr_range().end, data.as_ptr_range().end);
+        let len = encode_response_v1(id, &response, trailer, &mut buf).unwrap();
+        assert_eq!(&buf[..len], data);
+    }
+}
@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Runs arbitrary packets through the agent's full decode/encode path.
+
+#![no_main]
+
+use gimlet_inspector_protocol::power::HostPowerState;
+use gimlet_inspector_protocol::server::{dispatch, InspectorHandler};
+use gimlet_inspector_protocol::{
+    decode_request, FpgaConfigState, FpgaStatusResponseV0, FpgaStatusV0,
+    HostPowerStateResponseV0, IdentityResponseV0, IdentityV0,
+    PostCodesResponseV0, PowerRailsResponseV0, RingbufRequestV0,
+    RingbufResponseV0, SequencerRegistersResponseV0, TasksResponseV0,
+    TemperaturesResponseV0, ANY_RESPONSE_V1_MAX_SIZE, ERROR_RESPONSE_MARKER,
+    HOST_POWER_RESP_V0_TRAILER, POST_CODES_RESP_V0_TRAILER,
+    POWER_RAILS_RESP_V0_TRAILER, RESPONSE_V1_HEADER_SIZE, RESPONSE_V1_MARKER,
+    RINGBUF_RESP_V0_TRAILER, SEQ_REG_RESP_V0_TRAILER, TASKS_RESP_V0_TRAILER,
+    TEMPS_RESP_V0_TRAILER,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Handler that always succeeds with the largest response it can.
+struct Agent;
+
+impl InspectorHandler for Agent {
+    fn sequencer_registers(
+        &mut self,
+        trailer: &mut [u8; SEQ_REG_RESP_V0_TRAILER],
+    ) -> (SequencerRegistersResponseV0, usize) {
+        trailer.fill(0xa5);
+        (SequencerRegistersResponseV0::Success, trailer.len())
+    }
+
+    fn temperatures(
+        &mut self,
+        trailer: &mut [u8; TEMPS_RESP_V0_TRAILER],
+    ) -> (TemperaturesResponseV0, usize) {
+        trailer.fill(0xa5);
+        (TemperaturesResponseV0::Success, trailer.len())
+    }
+
+    fn power_rails(
+        &mut self,
+        trailer: &mut [u8; POWER_RAILS_RESP_V0_TRAILER],
+    ) -> (PowerRailsResponseV0, usize) {
+        trailer.fill(0xa5);
+        (PowerRailsResponseV0::Success, trailer.len())
+    }
+
+    fn host_power_state(
+        &mut self,
+        trailer: &mut [u8; HOST_POWER_RESP_V0_TRAILER],
+    ) -> (HostPowerStateResponseV0, usize) {
+        trailer.fill(0xa5);
+        let response = HostPowerStateResponseV0::Success {
+            state: HostPowerState::A0,
+            now: u64::MAX,
+        };
+        (response, trailer.len())
+    }
+
+    fn tasks(
+        &mut self,
+        trailer: &mut [u8; TASKS_RESP_V0_TRAILER],
+    ) -> (TasksResponseV0, usize) {
+        trailer.fill(0xa5);
+        (TasksResponseV0::Success { total: u16::MAX }, trailer.len())
+    }
+
+    fn identity(&mut self) -> IdentityResponseV0 {
+        IdentityResponseV0::Success(IdentityV0 {
+            model: [0xa5; 16],
+            revision: u32::MAX,
+            serial: [0xa5; 16],
+            image_version: u32::MAX,
+            git_commit: [0xa5; 20],
+            fpga_bitstream: u32::MAX,
+        })
+    }
+
+    fn fpga_status(&mut self) -> FpgaStatusResponseV0 {
+        FpgaStatusResponseV0::Success(FpgaStatusV0 {
+            state: FpgaConfigState::LoadFailed,
+            checksum: u32::MAX,
+            expected_checksum: u32::MAX,
+            error_code: u32::MAX,
+        })
+    }
+
+    fn post_codes(
+        &mut self,
+        trailer: &mut [u8; POST_CODES_RESP_V0_TRAILER],
+    ) -> (PostCodesResponseV0, usize) {
+        trailer.fill(0xa5);
+        (
+            PostCodesResponseV0::Success { total: u32::MAX },
+            trailer.len(),
+        )
+    }
+
+    fn ringbuf(
+        &mut self,
+        _request: RingbufRequestV0,
+        trailer: &mut [u8; RINGBUF_RESP_V0_TRAILER],
+    ) -> (RingbufResponseV0, usize) {
+        trailer.fill(0xa5);
+        let response = RingbufResponseV0::Success {
+            total: u32::MAX,
+            next: Some(u32::MAX),
+        };
+        (response, trailer.len())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+    let len = dispatch(data, &mut out, &mut Agent)
+        .expect("every packet gets a response");
+
+    // Anything that didn't decode must be answered with an error, with or
+    // without a V1 header.
+    if decode_request(data).is_err() {
+        let body = match out[0] {
+            RESPONSE_V1_MARKER => &out[RESPONSE_V1_HEADER_SIZE..len],
+            _ => &out[..len],
+        };
+        assert_eq!(body[0], ERROR_RESPONSE_MARKER);
+    }
+});
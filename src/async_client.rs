@@ -0,0 +1,658 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Async UDP client for the inspector agent, for polling many SPs at once.
+//! Requires the `async` feature, and a tokio runtime with I/O and time enabled.
+//!
+//! Where `client::InspectorClient` waits for one reply at a time, this client
+//! can have any number of requests outstanding over its one socket. A
+//! background task receives every reply and hands it to the request with the
+//! matching V1 ID. V0 requests carry no ID, so calls take turns sending them
+//! to each SP, and a V0 reply goes to whichever call's turn it is.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, Stream};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::{oneshot, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+
+use crate::client::{
+    canonical, is_from, ClientError, RequestVersion, DEFAULT_RETRIES,
+    DEFAULT_TIMEOUT,
+};
+use crate::query::Query;
+use crate::{
+    decode_response, decode_response_v1, encode_request, response_v1_id,
+    ErrorResponse, Request, ANY_RESPONSE_V1_MAX_SIZE, ERROR_RESPONSE_MARKER,
+    REQUEST_MAX_SIZE,
+};
+
+/// An async UDP socket for talking to many inspector agents at once.
+///
+/// As with `InspectorClient`, each call names the SP it should be sent to, and
+/// the request version is chosen by `RequestVersion`. Calls may be made
+/// concurrently from as many tasks as needed. Dropping the client stops its
+/// receive task.
+#[derive(Debug)]
+pub struct AsyncInspectorClient {
+    shared: Arc<Shared>,
+    receiver: JoinHandle<()>,
+    timeout: Duration,
+    retries: u32,
+    version: RequestVersion,
+}
+
+/// What a call to query `Q` returns: the response and its trailer.
+pub type Reply<Q> = Result<(<Q as Query>::Response, Vec<u8>), ClientError>;
+
+/// State shared between calls and the receive task.
+#[derive(Debug)]
+struct Shared {
+    socket: UdpSocket,
+    next_id: AtomicU32,
+    /// Requests still waiting for a reply, by ID. V0 requests are given an ID
+    /// too, but it isn't sent.
+    pending: Mutex<HashMap<u32, Pending>>,
+    /// Held by the call whose turn it is to send V0 requests to an SP, by SP
+    /// address in canonical form.
+    v0_turns: Mutex<HashMap<SocketAddr, Arc<tokio::sync::Mutex<()>>>>,
+    /// Agents found by `RequestVersion::Auto` to understand only V0, by
+    /// address in canonical form.
+    v0_agents: Mutex<HashSet<SocketAddr>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    sp: SocketAddr,
+    v0: bool,
+    reply: oneshot::Sender<Vec<u8>>,
+}
+
+impl AsyncInspectorClient {
+    /// Binds a new client socket to `addr` and starts its receive task on the
+    /// current runtime. Use port 0 to have the OS pick one.
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            socket: UdpSocket::bind(addr).await?,
+            next_id: AtomicU32::new(0),
+            pending: Mutex::default(),
+            v0_turns: Mutex::default(),
+            v0_agents: Mutex::default(),
+        });
+        let receiver = tokio::spawn(receive(Arc::clone(&shared)));
+        Ok(Self {
+            shared,
+            receiver,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            version: RequestVersion::default(),
+        })
+    }
+
+    /// Sets how long to wait for each reply before retrying.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times to resend a request that got no reply. Zero means
+    /// the request is sent exactly once.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets which version of `Request` to send.
+    pub fn with_version(mut self, version: RequestVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Returns the local address of the client socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// Sends query `Q` to `sp` and waits for its response, retrying on
+    /// timeout.
+    pub async fn call<Q: Query>(&self, sp: SocketAddr) -> Reply<Q> {
+        self.call_with_trailer::<Q>(sp, &[]).await
+    }
+
+    /// Like `call`, for queries that take a request trailer. `trailer` may be
+    /// at most `Q::MAX_TRAILER` bytes.
+    pub async fn call_with_trailer<Q: Query>(
+        &self,
+        sp: SocketAddr,
+        trailer: &[u8],
+    ) -> Reply<Q> {
+        let auto = self.version == RequestVersion::Auto;
+        let v0_agents = &self.shared.v0_agents;
+        let v1 = match self.version {
+            RequestVersion::V0 => false,
+            RequestVersion::V1 => true,
+            RequestVersion::Auto => {
+                !v0_agents.lock().unwrap().contains(&canonical(sp))
+            }
+        };
+        match self.send::<Q>(sp, trailer, v1, self.retries + 1).await {
+            Err(ClientError::ErrorResponse(
+                ErrorResponse::UnsupportedVersion,
+            )) if auto && v1 => {
+                v0_agents.lock().unwrap().insert(canonical(sp));
+                self.send::<Q>(sp, trailer, false, self.retries + 1).await
+            }
+            result => result,
+        }
+    }
+
+    /// Sends query `Q` to `sp` as a `Request::V1` if `v1` is set, or as a
+    /// `Request::V0` once it's this call's turn otherwise, up to `attempts`
+    /// times.
+    async fn send<Q: Query>(
+        &self,
+        sp: SocketAddr,
+        trailer: &[u8],
+        v1: bool,
+        attempts: u32,
+    ) -> Reply<Q> {
+        let mut request = [0; REQUEST_MAX_SIZE];
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let query = Q::QUERY;
+        let version = if v1 {
+            Request::V1 { id, query }
+        } else {
+            Request::V0(query)
+        };
+        let len = encode_request(&version, trailer, &mut request)?;
+        let request = &request[..len];
+
+        let _turn = if v1 {
+            None
+        } else {
+            Some(self.shared.v0_turn(sp).await)
+        };
+        if self.receiver.is_finished() {
+            return Err(receive_task_died());
+        }
+        let (reply, mut rx) = oneshot::channel();
+        let _registration = Registration::new(&self.shared, id, sp, !v1, reply);
+        for _ in 0..attempts {
+            self.shared.socket.send_to(request, sp).await?;
+            let reply = tokio::time::timeout(self.timeout, &mut rx);
+            let reply = match reply.await {
+                Ok(Ok(reply)) => reply,
+                // The receive task outlives every call unless its socket
+                // fails, or it panics.
+                Ok(Err(_)) => return Err(receive_task_died()),
+                Err(_) => continue,
+            };
+            let decoded = if v1 {
+                decode_response_v1(id, &reply)
+            } else {
+                decode_response(&reply)
+            };
+            return match decoded {
+                Ok((response, trailer)) => Ok((response, trailer.to_vec())),
+                Err(e) => Err(e.into()),
+            };
+        }
+        Err(ClientError::Timeout { attempts })
+    }
+
+    /// Sends query `Q` to every SP in `sps` at once, and yields each SP's
+    /// result as it arrives.
+    pub fn call_many<Q: Query>(
+        &self,
+        sps: impl IntoIterator<Item = SocketAddr>,
+    ) -> impl Stream<Item = (SocketAddr, Reply<Q>)> + '_ {
+        sps.into_iter()
+            .map(|sp| async move { (sp, self.call::<Q>(sp).await) })
+            .collect::<FuturesUnordered<_>>()
+    }
+}
+
+impl Shared {
+    /// Waits until no other call has V0 requests outstanding to `sp`. The
+    /// turn lasts until the returned guard is dropped.
+    async fn v0_turn(&self, sp: SocketAddr) -> OwnedMutexGuard<()> {
+        let sp = canonical(sp);
+        let turn =
+            Arc::clone(self.v0_turns.lock().unwrap().entry(sp).or_default());
+        turn.lock_owned().await
+    }
+}
+
+impl Drop for AsyncInspectorClient {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// Entry in `Shared::pending` for the duration of one call, removed when the
+/// call returns or is cancelled.
+struct Registration<'a> {
+    shared: &'a Shared,
+    id: u32,
+}
+
+impl<'a> Registration<'a> {
+    fn new(
+        shared: &'a Shared,
+        id: u32,
+        sp: SocketAddr,
+        v0: bool,
+        reply: oneshot::Sender<Vec<u8>>,
+    ) -> Self {
+        shared
+            .pending
+            .lock()
+            .unwrap()
+            .insert(id, Pending { sp, v0, reply });
+        Self { shared, id }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn receive_task_died() -> ClientError {
+    io::Error::other("receive task died").into()
+}
+
+/// Receives replies for as long as the client exists, handing each to the
+/// pending request it answers. Replies that answer nothing pending -- late
+/// replies to retried requests, or packets from the wrong address -- are
+/// dropped.
+///
+/// If the socket fails, every pending request is failed, and so is every call
+/// made from then on.
+async fn receive(shared: Arc<Shared>) {
+    let mut buf = [0; ANY_RESPONSE_V1_MAX_SIZE];
+    loop {
+        let (n, from) = match shared.socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            // These concern single packets, such as ICMP errors for earlier
+            // sends on some platforms, so keep listening.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                continue;
+            }
+            Err(_) => {
+                // Dropping the reply senders wakes their calls.
+                shared.pending.lock().unwrap().clear();
+                return;
+            }
+        };
+        let packet = &buf[..n];
+        let mut pending = shared.pending.lock().unwrap();
+        // A packet with a header answers the V1 request it names. A bare
+        // `UnsupportedVersion` could answer any V1 request outstanding to the
+        // SP, since V0 is never unsupported. Anything else answers the SP's V0
+        // request, of which it has at most one outstanding.
+        let (ids, v0): (Vec<u32>, bool) = match response_v1_id(packet) {
+            Some(id) => (vec![id], false),
+            None => {
+                let unsupported = matches!(
+                    packet,
+                    [ERROR_RESPONSE_MARKER, rest @ ..]
+                        if matches!(
+                            hubpack::deserialize(rest),
+                            Ok((ErrorResponse::UnsupportedVersion, _)),
+                        )
+                );
+                (pending.keys().copied().collect(), !unsupported)
+            }
+        };
+        for id in ids {
+            let answers = |p: &Pending| is_from(p.sp, from) && p.v0 == v0;
+            if pending.get(&id).is_some_and(answers) {
+                let p = pending.remove(&id).unwrap();
+                // The call may have just given up; that's fine.
+                let _ = p.reply.send(packet.to_vec());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockAgent, MockReply};
+    use crate::{
+        decode_request, encode_error_response, encode_response,
+        encode_response_v1, query, CapabilitiesResponseV0, CapabilitiesV0,
+        ErrorResponse, FpgaStatusResponseV0, QueryV0, TasksResponseV0,
+    };
+    use futures::StreamExt;
+    use std::future::Future;
+    use std::thread;
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    async fn client() -> AsyncInspectorClient {
+        AsyncInspectorClient::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(100))
+            .with_retries(0)
+            .with_version(RequestVersion::V1)
+    }
+
+    #[test]
+    fn call_many_agents() {
+        let agents: Vec<MockAgent> =
+            (0..8).map(|_| MockAgent::start().unwrap()).collect();
+        for (total, agent) in agents.iter().enumerate() {
+            agent.push(
+                QueryV0::Tasks,
+                MockReply::Tasks(
+                    TasksResponseV0::Success {
+                        total: total as u16,
+                    },
+                    vec![],
+                ),
+            );
+        }
+        // Nobody ever answers on this socket.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = silent.local_addr().unwrap();
+
+        let sps = agents.iter().map(MockAgent::addr).chain([silent]);
+        let results: HashMap<_, _> = block_on(async {
+            client()
+                .await
+                .call_many::<query::Tasks>(sps)
+                .collect()
+                .await
+        });
+        assert_eq!(results.len(), agents.len() + 1);
+        for (total, agent) in agents.iter().enumerate() {
+            let (response, _) = results[&agent.addr()].as_ref().unwrap();
+            let expected = TasksResponseV0::Success {
+                total: total as u16,
+            };
+            assert_eq!(*response, expected);
+        }
+        assert!(matches!(
+            results[&silent],
+            Err(ClientError::Timeout { attempts: 1 }),
+        ));
+    }
+
+    #[test]
+    fn retries_lost_replies() {
+        let agent = MockAgent::start().unwrap();
+        agent.push(QueryV0::FpgaStatus, MockReply::Drop);
+        agent.push(
+            QueryV0::FpgaStatus,
+            MockReply::FpgaStatus(FpgaStatusResponseV0::SequencerTaskDead),
+        );
+        let (response, trailer) = block_on(async {
+            client()
+                .await
+                .with_retries(1)
+                .call::<query::FpgaStatus>(agent.addr())
+                .await
+                .unwrap()
+        });
+        assert_eq!(response, FpgaStatusResponseV0::SequencerTaskDead);
+        assert!(trailer.is_empty());
+        assert_eq!(agent.received().len(), 2);
+    }
+
+    #[test]
+    fn v0_only_agents() {
+        let old = MockAgent::start_v0_only().unwrap();
+        let new = MockAgent::start().unwrap();
+        for (total, agent) in [&old, &new].into_iter().enumerate() {
+            agent.set_default(
+                QueryV0::Tasks,
+                MockReply::Tasks(
+                    TasksResponseV0::Success {
+                        total: total as u16,
+                    },
+                    vec![],
+                ),
+            );
+        }
+
+        block_on(async {
+            // Silence is taken for lost packets, not for a V0-only agent.
+            let client = client().await.with_version(RequestVersion::Auto);
+            assert!(matches!(
+                client.call::<query::Tasks>(old.addr()).await,
+                Err(ClientError::Timeout { attempts: 1 }),
+            ));
+
+            // Every agent understands V0.
+            let client = client.with_version(RequestVersion::V0);
+            let results: HashMap<_, _> = client
+                .call_many::<query::Tasks>([old.addr(), new.addr()])
+                .collect()
+                .await;
+            let (response, _) = results[&old.addr()].as_ref().unwrap();
+            assert_eq!(*response, TasksResponseV0::Success { total: 0 });
+            let (response, _) = results[&new.addr()].as_ref().unwrap();
+            assert_eq!(*response, TasksResponseV0::Success { total: 1 });
+
+            // Concurrent V0 calls to one SP each get their own reply.
+            let (tasks, fpga) = futures::future::join(
+                client.call::<query::Tasks>(old.addr()),
+                client.call::<query::FpgaStatus>(old.addr()),
+            )
+            .await;
+            assert_eq!(tasks.unwrap().0, TasksResponseV0::Success { total: 0 });
+            assert!(matches!(
+                fpga.unwrap().0,
+                FpgaStatusResponseV0::Success(_),
+            ));
+        });
+        let requests: Vec<_> = old.received().iter().map(Request::id).collect();
+        assert!(matches!(requests[..], [Some(_), None, None, None]));
+        assert_eq!(new.received(), [Request::V0(QueryV0::Tasks)]);
+    }
+
+    #[test]
+    fn auto_falls_back_on_unsupported_version() {
+        // Refuses every V1 request, as an agent that predates it does.
+        let agent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sp = agent.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut received: Vec<Request> = Vec::new();
+            let mut buf = [0; REQUEST_MAX_SIZE];
+            let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+            // One V0 request per call.
+            while received.iter().filter(|r| r.id().is_none()).count() < 3 {
+                let (n, from) = agent.recv_from(&mut buf).unwrap();
+                let (request, _) = decode_request(&buf[..n]).unwrap();
+                received.push(request);
+                let n = match request {
+                    Request::V1 { .. } => encode_error_response(
+                        ErrorResponse::UnsupportedVersion,
+                        &mut out,
+                    ),
+                    Request::V0(QueryV0::Tasks) => encode_response(
+                        &TasksResponseV0::Success { total: 7 },
+                        &[],
+                        &mut out,
+                    ),
+                    Request::V0(QueryV0::FpgaStatus) => encode_response(
+                        &FpgaStatusResponseV0::FpgaStatusReadFailed,
+                        &[],
+                        &mut out,
+                    ),
+                    request => panic!("unexpected {request:?}"),
+                }
+                .unwrap();
+                agent.send_to(&out[..n], from).unwrap();
+            }
+            received
+        });
+
+        block_on(async {
+            // Neither call's V0 request is failed by the other's refusal.
+            let client = client().await.with_version(RequestVersion::Auto);
+            let (tasks, fpga) = futures::future::join(
+                client.call::<query::Tasks>(sp),
+                client.call::<query::FpgaStatus>(sp),
+            )
+            .await;
+            assert_eq!(tasks.unwrap().0, TasksResponseV0::Success { total: 7 });
+            assert_eq!(
+                fpga.unwrap().0,
+                FpgaStatusResponseV0::FpgaStatusReadFailed,
+            );
+            client.call::<query::Tasks>(sp).await.unwrap();
+        });
+        let received = handle.join().unwrap();
+        assert!(received.iter().filter(|r| r.id().is_some()).count() <= 2);
+        assert_eq!(received.last(), Some(&Request::V0(QueryV0::Tasks)));
+    }
+
+    #[test]
+    fn bare_errors_routed_by_version() {
+        block_on(async {
+            let client = client().await;
+            let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let sp = agent.local_addr().unwrap();
+            let (v1, v1_rx) = oneshot::channel();
+            let (v0, mut v0_rx) = oneshot::channel();
+            let _v1 = Registration::new(&client.shared, 100, sp, false, v1);
+            let _v0 = Registration::new(&client.shared, 101, sp, true, v0);
+
+            let to = client.local_addr().unwrap();
+            let error = |error| {
+                let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+                let n = encode_error_response(error, &mut out).unwrap();
+                out[..n].to_vec()
+            };
+            let unsupported = error(ErrorResponse::UnsupportedVersion);
+            agent.send_to(&unsupported, to).await.unwrap();
+            assert_eq!(v1_rx.await.unwrap(), unsupported);
+            assert!(v0_rx.try_recv().is_err());
+
+            let unknown = error(ErrorResponse::UnknownQuery);
+            agent.send_to(&unknown, to).await.unwrap();
+            assert_eq!(v0_rx.await.unwrap(), unknown);
+        });
+    }
+
+    #[test]
+    fn ipv4_agent_from_ipv6_socket() {
+        let agent = MockAgent::start().unwrap();
+        assert!(agent.addr().is_ipv4());
+        let (response, _) = block_on(async {
+            AsyncInspectorClient::bind("[::]:0")
+                .await
+                .unwrap()
+                .with_timeout(Duration::from_millis(100))
+                .with_retries(0)
+                .call::<query::FpgaStatus>(agent.addr())
+                .await
+                .unwrap()
+        });
+        assert!(matches!(response, FpgaStatusResponseV0::Success(_)));
+    }
+
+    #[test]
+    fn replies_out_of_order() {
+        let agent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sp = agent.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut replies = Vec::new();
+            let mut buf = [0; REQUEST_MAX_SIZE];
+            let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+            for _ in 0..2 {
+                let (n, from) = agent.recv_from(&mut buf).unwrap();
+                let (request, _) = decode_request(&buf[..n]).unwrap();
+                let id = request.id().unwrap();
+                let n = match request.query() {
+                    QueryV0::Capabilities => encode_response_v1(
+                        id,
+                        &CapabilitiesResponseV0::Success(
+                            CapabilitiesV0::CURRENT,
+                        ),
+                        &[],
+                        &mut out,
+                    ),
+                    QueryV0::FpgaStatus => encode_response_v1(
+                        id,
+                        &FpgaStatusResponseV0::FpgaStatusReadFailed,
+                        &[],
+                        &mut out,
+                    ),
+                    query => panic!("unexpected {query:?}"),
+                }
+                .unwrap();
+                replies.push((out[..n].to_vec(), from));
+            }
+            // Neither request is waiting for a reply to some other ID.
+            let n = encode_response_v1(
+                u32::MAX,
+                &FpgaStatusResponseV0::SequencerTaskDead,
+                &[],
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], replies[0].1).unwrap();
+            for (reply, from) in replies.into_iter().rev() {
+                agent.send_to(&reply, from).unwrap();
+            }
+
+            // A bare `UnsupportedVersion` answers any V1 request.
+            let (_, from) = agent.recv_from(&mut buf).unwrap();
+            let n = encode_error_response(
+                ErrorResponse::UnsupportedVersion,
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], from).unwrap();
+        });
+
+        block_on(async {
+            let client = client().await;
+            let (caps, fpga) = futures::future::join(
+                client.call::<query::Capabilities>(sp),
+                client.call::<query::FpgaStatus>(sp),
+            )
+            .await;
+            assert_eq!(
+                caps.unwrap().0,
+                CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT),
+            );
+            assert_eq!(
+                fpga.unwrap().0,
+                FpgaStatusResponseV0::FpgaStatusReadFailed,
+            );
+            assert!(matches!(
+                client.call::<query::Tasks>(sp).await,
+                Err(ClientError::ErrorResponse(
+                    ErrorResponse::UnsupportedVersion
+                )),
+            ));
+        });
+        handle.join().unwrap();
+    }
+}
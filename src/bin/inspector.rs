@@ -0,0 +1,1155 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Command-line tool for querying inspector agents.
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+
+use gimlet_inspector_protocol::async_client::AsyncInspectorClient;
+use gimlet_inspector_protocol::client::{
+    ClientError, InspectorClient, RequestVersion, DEFAULT_RETRIES,
+};
+use gimlet_inspector_protocol::power::PowerTransition;
+use gimlet_inspector_protocol::records::{
+    decode_prefixed_records, decode_records,
+};
+use gimlet_inspector_protocol::sensors::{
+    PowerRailRecord, SensorStatus, TemperatureRecord,
+};
+use gimlet_inspector_protocol::sweep::{
+    sweep_sequencer_registers, SequencerSweep,
+};
+use gimlet_inspector_protocol::tasks::TaskRecord;
+use gimlet_inspector_protocol::{
+    query, CapabilitiesResponseV0, FpgaStatusResponseV0,
+    HostPowerStateResponseV0, IdentityResponseV0, PostCodesResponseV0,
+    PowerRailsResponseV0, QueryV0, SequencerRegistersResponseV0,
+    TasksResponseV0, TemperaturesResponseV0,
+};
+
+#[derive(Parser)]
+#[command(about = "Query Gimlet inspector agents")]
+struct Args {
+    /// How long to wait for each reply, in milliseconds.
+    #[arg(long, default_value_t = 500)]
+    timeout_ms: u64,
+    /// How many times to resend a request that got no reply.
+    #[arg(long, default_value_t = DEFAULT_RETRIES)]
+    retries: u32,
+    /// Local address to bind the client socket to.
+    #[arg(long, default_value = "[::]:0")]
+    bind: SocketAddr,
+    /// Request version to send. `auto` sends V1, falling back to V0 for
+    /// agents that refuse it; agents too old to answer V1 at all need `v0`.
+    #[arg(long, value_enum, default_value_t = Protocol::Auto)]
+    protocol: Protocol,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send a single query to one agent and print the response.
+    Query {
+        /// Address of the agent, e.g. `[fe80::1%2]:23547`.
+        sp: SocketAddr,
+        /// Query to send.
+        query: Query,
+        /// Index of the ringbuf to read, for the `ringbuf` query.
+        #[arg(long, default_value_t = 0)]
+        ringbuf: u16,
+        /// How to print the response.
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Collect sequencer registers from many agents at once, and show where
+    /// they differ.
+    Sweep {
+        /// Addresses of the agents.
+        #[arg(required_unless_present = "sp_file")]
+        sps: Vec<SocketAddr>,
+        /// File listing more agent addresses, one per line. Blank lines and
+        /// lines starting with `#` are ignored.
+        #[arg(long)]
+        sp_file: Option<PathBuf>,
+        /// How to print the results.
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+}
+
+/// Command-line names for `QueryV0` variants.
+#[derive(Copy, Clone, ValueEnum)]
+enum Query {
+    SequencerRegisters,
+    Capabilities,
+    Temperatures,
+    PowerRails,
+    HostPowerState,
+    Tasks,
+    Identity,
+    FpgaStatus,
+    PostCodes,
+    Ringbuf,
+}
+
+impl From<Query> for QueryV0 {
+    fn from(q: Query) -> Self {
+        match q {
+            Query::SequencerRegisters => Self::SequencerRegisters,
+            Query::Capabilities => Self::Capabilities,
+            Query::Temperatures => Self::Temperatures,
+            Query::PowerRails => Self::PowerRails,
+            Query::HostPowerState => Self::HostPowerState,
+            Query::Tasks => Self::Tasks,
+            Query::Identity => Self::Identity,
+            Query::FpgaStatus => Self::FpgaStatus,
+            Query::PostCodes => Self::PostCodes,
+            Query::Ringbuf => Self::Ringbuf,
+        }
+    }
+}
+
+/// Command-line names for `RequestVersion` variants.
+#[derive(Copy, Clone, ValueEnum)]
+enum Protocol {
+    V0,
+    V1,
+    Auto,
+}
+
+impl From<Protocol> for RequestVersion {
+    fn from(p: Protocol) -> Self {
+        match p {
+            Protocol::V0 => Self::V0,
+            Protocol::V1 => Self::V1,
+            Protocol::Auto => Self::Auto,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    /// Decoded, human-readable fields.
+    Table,
+    /// Decoded fields as a JSON object.
+    Json,
+    /// Hex dump of the response trailer, or of the whole reply packet for
+    /// queries that have no trailer.
+    Hex,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let result = match args.command {
+        Command::Query {
+            sp,
+            query,
+            ringbuf,
+            format,
+        } => {
+            let client = match InspectorClient::bind(args.bind) {
+                Ok(client) => client
+                    .with_timeout(timeout)
+                    .with_retries(args.retries)
+                    .with_version(args.protocol.into()),
+                Err(e) => {
+                    eprintln!("error: can't bind {}: {e}", args.bind);
+                    return ExitCode::FAILURE;
+                }
+            };
+            query_one(&client, sp, query.into(), ringbuf, format)
+        }
+        Command::Sweep {
+            mut sps,
+            sp_file,
+            format,
+        } => {
+            if let Some(path) = sp_file {
+                match read_sp_file(&path) {
+                    Ok(more) => sps.extend(more),
+                    Err(e) => {
+                        eprintln!("error: can't read {}: {e}", path.display());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("can't start tokio runtime");
+            let sweep = runtime.block_on(async {
+                let client = AsyncInspectorClient::bind(args.bind).await?;
+                let client = client
+                    .with_timeout(timeout)
+                    .with_retries(args.retries)
+                    .with_version(args.protocol.into());
+                Ok::<_, io::Error>(
+                    sweep_sequencer_registers(&client, &sps).await,
+                )
+            });
+            match sweep {
+                Ok(sweep) => Ok(format_sweep(&sweep, format)),
+                Err(e) => {
+                    eprintln!("error: can't bind {}: {e}", args.bind);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    };
+    match result {
+        Ok(output) => {
+            print!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Issues `query` to `sp`, returning the response formatted as requested.
+/// `ringbuf` is only used by `QueryV0::Ringbuf`, which is repeated until the
+/// whole ringbuf has been read.
+fn query_one(
+    client: &InspectorClient,
+    sp: SocketAddr,
+    query: QueryV0,
+    ringbuf: u16,
+    format: Format,
+) -> Result<String, ClientError> {
+    Ok(match query {
+        QueryV0::SequencerRegisters => {
+            let (response, trailer) = client.sequencer_registers(sp)?;
+            format_sequencer_registers(response, &trailer, format)
+        }
+        QueryV0::Capabilities => {
+            let (response, _, packet) =
+                client.call_with_packet::<query::Capabilities>(sp, &[])?;
+            format_capabilities(response, &packet, format)
+        }
+        QueryV0::Temperatures => {
+            let (response, trailer) = client.temperatures(sp)?;
+            format_temperatures(response, &trailer, format)
+        }
+        QueryV0::PowerRails => {
+            let (response, trailer) = client.power_rails(sp)?;
+            format_power_rails(response, &trailer, format)
+        }
+        QueryV0::HostPowerState => {
+            let (response, trailer) = client.host_power_state(sp)?;
+            format_host_power_state(response, &trailer, format)
+        }
+        QueryV0::Tasks => {
+            let (response, trailer) = client.tasks(sp)?;
+            format_tasks(response, &trailer, format)
+        }
+        QueryV0::Identity => {
+            let (response, _, packet) =
+                client.call_with_packet::<query::Identity>(sp, &[])?;
+            format_identity(response, &packet, format)
+        }
+        QueryV0::FpgaStatus => {
+            let (response, _, packet) =
+                client.call_with_packet::<query::FpgaStatus>(sp, &[])?;
+            format_fpga_status(response, &packet, format)
+        }
+        QueryV0::PostCodes => {
+            let (response, trailer) = client.post_codes(sp)?;
+            format_post_codes(response, &trailer, format)
+        }
+        QueryV0::Ringbuf => {
+            format_ringbuf(ringbuf, &client.read_ringbuf(sp, ringbuf)?, format)
+        }
+    })
+}
+
+/// Reads a list of agent addresses for `sweep`, one per line.
+fn read_sp_file(path: &Path) -> io::Result<Vec<SocketAddr>> {
+    let mut sps = Vec::new();
+    for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sp = line.parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {line:?}: {e}", i + 1),
+            )
+        })?;
+        sps.push(sp);
+    }
+    Ok(sps)
+}
+
+fn format_sequencer_registers(
+    response: SequencerRegistersResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let out = json!({
+                "response": response,
+                "trailer": hex(trailer),
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            // The register layout isn't known yet, so show the raw dump.
+            let mut out = format!("{:<12} {response:?}\n", "response");
+            out += &hex_dump(trailer);
+            out
+        }
+    }
+}
+
+fn format_capabilities(
+    response: CapabilitiesResponseV0,
+    packet: &[u8],
+    format: Format,
+) -> String {
+    let CapabilitiesResponseV0::Success(caps) = response;
+    let queries: Vec<String> = QueryV0::ALL
+        .into_iter()
+        .filter(|q| caps.supports(*q))
+        .map(|q| format!("{q:?}"))
+        .collect();
+    match format {
+        // No trailer, so dump the packet as received.
+        Format::Hex => hex_dump(packet),
+        Format::Json => {
+            let out = json!({
+                "max_request_version": caps.max_request_version,
+                "queries": queries,
+                "query_bitmap": hex(&caps.queries),
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => format!(
+            "{:<12} V{}\n{:<12} {}\n",
+            "max version",
+            caps.max_request_version,
+            "queries",
+            queries.join(", "),
+        ),
+    }
+}
+
+fn format_temperatures(
+    response: TemperaturesResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    let records: Vec<_> =
+        decode_records::<TemperatureRecord>(trailer).collect();
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let sensors: Vec<_> = records
+                .iter()
+                .map(|r| match r {
+                    Ok(r) => json!({
+                        "sensor": r.sensor,
+                        "kind": format!("{:?}", r.kind),
+                        "millidegrees_c": r.value,
+                        "status": format!("{:?}", r.status),
+                    }),
+                    Err(e) => json!({ "error": format!("{e:?}") }),
+                })
+                .collect();
+            let out = json!({
+                "response": response,
+                "sensors": sensors,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let mut out = format!("{:<12} {response:?}\n", "response");
+            for r in &records {
+                match r {
+                    Ok(r) if r.status == SensorStatus::Ok => writeln!(
+                        out,
+                        "{:<12} {:<16} {:>7} C",
+                        r.sensor,
+                        format!("{:?}", r.kind),
+                        milli(r.value),
+                    ),
+                    Ok(r) => writeln!(
+                        out,
+                        "{:<12} {:<16} {:?}",
+                        r.sensor,
+                        format!("{:?}", r.kind),
+                        r.status,
+                    ),
+                    Err(e) => writeln!(out, "{:<12} can't decode: {e:?}", "?"),
+                }
+                .unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_power_rails(
+    response: PowerRailsResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    let records: Vec<_> = decode_records::<PowerRailRecord>(trailer).collect();
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let rails: Vec<_> = records
+                .iter()
+                .map(|r| match r {
+                    Ok(r) => json!({
+                        "rail": r.rail,
+                        "millivolts": r.voltage,
+                        "milliamps": r.current,
+                        "status": format!("{:?}", r.status),
+                    }),
+                    Err(e) => json!({ "error": format!("{e:?}") }),
+                })
+                .collect();
+            let out = json!({
+                "response": response,
+                "rails": rails,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let mut out = format!("{:<12} {response:?}\n", "response");
+            for r in &records {
+                match r {
+                    Ok(r) if r.status == SensorStatus::Ok => writeln!(
+                        out,
+                        "{:<12} {:>7} V {:>7} A",
+                        r.rail,
+                        milli(r.voltage),
+                        milli(r.current),
+                    ),
+                    Ok(r) => writeln!(out, "{:<12} {:?}", r.rail, r.status,),
+                    Err(e) => writeln!(out, "{:<12} can't decode: {e:?}", "?"),
+                }
+                .unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_host_power_state(
+    response: HostPowerStateResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    let history: Vec<_> = decode_records::<PowerTransition>(trailer).collect();
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let (state, now) = match response {
+                HostPowerStateResponseV0::Success { state, now } => {
+                    (json!(format!("{state:?}")), json!(now))
+                }
+                _ => (json!(null), json!(null)),
+            };
+            let history: Vec<_> = history
+                .iter()
+                .map(|t| match t {
+                    Ok(t) => json!({
+                        "timestamp_ms": t.timestamp,
+                        "from": format!("{:?}", t.from),
+                        "to": format!("{:?}", t.to),
+                        "cause": format!("{:?}", t.cause),
+                    }),
+                    Err(e) => json!({ "error": format!("{e:?}") }),
+                })
+                .collect();
+            let out = json!({
+                "response": response,
+                "state": state,
+                "now_ms": now,
+                "history": history,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let HostPowerStateResponseV0::Success { state, now } = response
+            else {
+                return format!("{:<12} {response:?}\n", "response");
+            };
+            let mut out = format!(
+                "{:<12} Success\n{:<12} {state:?}\n{:<12} {now} ms\n",
+                "response", "state", "now",
+            );
+            for t in &history {
+                match t {
+                    Ok(t) => writeln!(
+                        out,
+                        "{:<12} {:?} -> {:?} ({:?})",
+                        format!("{} ms", t.timestamp),
+                        t.from,
+                        t.to,
+                        t.cause,
+                    ),
+                    Err(e) => writeln!(out, "{:<12} can't decode: {e:?}", "?"),
+                }
+                .unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_tasks(
+    response: TasksResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    let TasksResponseV0::Success { total } = response;
+    let tasks: Vec<_> = decode_records::<TaskRecord>(trailer).collect();
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let tasks: Vec<_> = tasks
+                .iter()
+                .map(|t| match t {
+                    Ok(t) => json!({
+                        "index": t.index,
+                        "generation": t.generation,
+                        "restarts": t.restarts,
+                        "state": format!("{:?}", t.state),
+                    }),
+                    Err(e) => json!({ "error": format!("{e:?}") }),
+                })
+                .collect();
+            let out = json!({
+                "response": response,
+                "total": total,
+                "tasks": tasks,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let mut out = format!(
+                "{:<12} Success\n{:<12} {total}\n{:<12} {:<24} {:>4} {:>8}\n",
+                "response", "tasks", "index", "state", "gen", "restarts",
+            );
+            for t in &tasks {
+                match t {
+                    Ok(t) => writeln!(
+                        out,
+                        "{:<12} {:<24} {:>4} {:>8}",
+                        t.index,
+                        format!("{:?}", t.state),
+                        t.generation,
+                        t.restarts,
+                    ),
+                    Err(e) => writeln!(out, "{:<12} can't decode: {e:?}", "?"),
+                }
+                .unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_identity(
+    response: IdentityResponseV0,
+    packet: &[u8],
+    format: Format,
+) -> String {
+    let IdentityResponseV0::Success(id) = response;
+    let model = id.model_str().map_or_else(|| hex(&id.model), str::to_owned);
+    let serial = id
+        .serial_str()
+        .map_or_else(|| hex(&id.serial), str::to_owned);
+    match format {
+        // No trailer, so dump the packet as received.
+        Format::Hex => hex_dump(packet),
+        Format::Json => {
+            let out = json!({
+                "model": model,
+                "revision": id.revision,
+                "serial": serial,
+                "image_version": id.image_version,
+                "git_commit": hex(&id.git_commit),
+                "fpga_bitstream": id.fpga_bitstream,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let mut out = String::new();
+            for (name, value) in [
+                ("model", model),
+                ("revision", format!("{}", id.revision)),
+                ("serial", serial),
+                ("image", format!("{:#010x}", id.image_version)),
+                ("commit", hex(&id.git_commit)),
+                ("fpga", format!("{:#010x}", id.fpga_bitstream)),
+            ] {
+                writeln!(out, "{name:<12} {value}").unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_fpga_status(
+    response: FpgaStatusResponseV0,
+    packet: &[u8],
+    format: Format,
+) -> String {
+    let status = match response {
+        FpgaStatusResponseV0::Success(status) => Some(status),
+        _ => None,
+    };
+    match format {
+        // No trailer, so dump the packet as received.
+        Format::Hex => hex_dump(packet),
+        Format::Json => {
+            let out = json!({ "response": response });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let Some(s) = status else {
+                return format!("{:<12} {response:?}\n", "response");
+            };
+            let mut out = format!("{:<12} Success\n", "response");
+            for (name, value) in [
+                ("state", format!("{:?}", s.state)),
+                ("checksum", format!("{:#010x}", s.checksum)),
+                ("expected", format!("{:#010x}", s.expected_checksum)),
+                ("error code", format!("{:#010x}", s.error_code)),
+            ] {
+                writeln!(out, "{name:<12} {value}").unwrap();
+            }
+            out
+        }
+    }
+}
+
+fn format_post_codes(
+    response: PostCodesResponseV0,
+    trailer: &[u8],
+    format: Format,
+) -> String {
+    let codes = match response {
+        PostCodesResponseV0::Success { .. } => Some(
+            decode_prefixed_records::<u32>(trailer)
+                .and_then(|codes| codes.collect::<Result<Vec<_>, _>>()),
+        ),
+        _ => None,
+    };
+    match format {
+        Format::Hex => hex_dump(trailer),
+        Format::Json => {
+            let codes = match codes {
+                Some(Ok(codes)) => json!(codes),
+                Some(Err(e)) => json!({ "error": format!("{e:?}") }),
+                None => json!(null),
+            };
+            let out = json!({
+                "response": response,
+                "codes": codes,
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let mut out = match response {
+                PostCodesResponseV0::Success { total } => format!(
+                    "{:<12} Success\n{:<12} {total}\n",
+                    "response", "total",
+                ),
+                _ => format!("{:<12} {response:?}\n", "response"),
+            };
+            match codes {
+                Some(Ok(codes)) => {
+                    for code in codes {
+                        writeln!(out, "{code:#010x}").unwrap();
+                    }
+                }
+                Some(Err(e)) => {
+                    writeln!(out, "{:<12} can't decode: {e:?}", "codes")
+                        .unwrap();
+                    out += &hex_dump(trailer);
+                }
+                None => (),
+            }
+            out
+        }
+    }
+}
+
+fn format_ringbuf(ringbuf: u16, data: &[u8], format: Format) -> String {
+    match format {
+        Format::Hex => hex_dump(data),
+        Format::Json => {
+            let out = json!({
+                "ringbuf": ringbuf,
+                "data": hex(data),
+            });
+            format!("{out:#}\n")
+        }
+        Format::Table => format!(
+            "{:<12} {ringbuf}\n{:<12} {} bytes\n{}",
+            "ringbuf",
+            "size",
+            data.len(),
+            hex_dump(data),
+        ),
+    }
+}
+
+fn format_sweep(sweep: &SequencerSweep, format: Format) -> String {
+    let diffs = sweep.register_diffs();
+    match format {
+        Format::Hex => {
+            let mut out = String::new();
+            for (sp, dump) in sweep.dumps() {
+                writeln!(out, "{sp}").unwrap();
+                out += &hex_dump(dump);
+            }
+            out
+        }
+        Format::Json => {
+            let sleds: Vec<_> = sweep
+                .results
+                .iter()
+                .map(|(sp, result)| match result {
+                    Ok((response, trailer)) => json!({
+                        "sp": sp,
+                        "response": response,
+                        "trailer": hex(trailer),
+                    }),
+                    Err(e) => json!({ "sp": sp, "error": e.to_string() }),
+                })
+                .collect();
+            let diffs: Vec<_> = diffs
+                .iter()
+                .map(|diff| {
+                    let outliers: Vec<_> = diff
+                        .outliers
+                        .iter()
+                        .map(|(sp, value)| json!({ "sp": sp, "value": value }))
+                        .collect();
+                    json!({
+                        "offset": diff.offset,
+                        "common": diff.common,
+                        "outliers": outliers,
+                    })
+                })
+                .collect();
+            let out = json!({ "sleds": sleds, "diffs": diffs });
+            format!("{out:#}\n")
+        }
+        Format::Table => {
+            let width = sweep
+                .results
+                .iter()
+                .map(|(sp, _)| sp.to_string().len())
+                .max()
+                .unwrap_or(0)
+                .max("sp".len());
+            let mut out = format!("{:<width$}  response\n", "sp");
+            for (sp, result) in &sweep.results {
+                let sp = sp.to_string();
+                match result {
+                    Ok((response, _)) => {
+                        writeln!(out, "{sp:<width$}  {response:?}").unwrap()
+                    }
+                    Err(e) => {
+                        writeln!(out, "{sp:<width$}  error: {e}").unwrap()
+                    }
+                }
+            }
+            if !diffs.is_empty() {
+                write!(
+                    out,
+                    "\n{:<6}  {:<6}  {:<width$}  value\n",
+                    "offset", "common", "sp"
+                )
+                .unwrap();
+                for diff in &diffs {
+                    for (i, (sp, value)) in diff.outliers.iter().enumerate() {
+                        let (offset, common) = if i == 0 {
+                            (
+                                format!("{:#04x}", diff.offset),
+                                format!("{:#04x}", diff.common),
+                            )
+                        } else {
+                            (String::new(), String::new())
+                        };
+                        let sp = sp.to_string();
+                        let value = format!("{value:#04x}");
+                        writeln!(
+                            out,
+                            "{offset:<6}  {common:<6}  {sp:<width$}  {value}",
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+/// Formats a value in thousandths as a decimal.
+fn milli(value: i32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let abs = value.unsigned_abs();
+    format!("{sign}{}.{:03}", abs / 1000, abs % 1000)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Formats `bytes` as lines of 16, each prefixed with its offset.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let line: Vec<String> =
+            line.iter().map(|b| format!("{b:02x}")).collect();
+        writeln!(out, "{:04x}: {}", i * 16, line.join(" ")).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_lines() {
+        let bytes: Vec<u8> = (0..18).collect();
+        assert_eq!(
+            hex_dump(&bytes),
+            "0000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n\
+             0010: 10 11\n",
+        );
+    }
+
+    #[test]
+    fn sequencer_registers_table() {
+        let dump: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            format_sequencer_registers(
+                SequencerRegistersResponseV0::Success,
+                &dump,
+                Format::Table
+            ),
+            "response     Success\n\
+             0000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n\
+             0010: 10 11 12 13\n",
+        );
+        assert_eq!(
+            format_sequencer_registers(
+                SequencerRegistersResponseV0::SequencerTaskDead,
+                &[],
+                Format::Table
+            ),
+            "response     SequencerTaskDead\n",
+        );
+    }
+
+    #[test]
+    fn temperatures_table() {
+        use gimlet_inspector_protocol::records::encode_records;
+        use gimlet_inspector_protocol::sensors::TemperatureSensorKind;
+
+        let records = [
+            TemperatureRecord {
+                sensor: 1,
+                kind: TemperatureSensorKind::Cpu,
+                value: 45_500,
+                status: SensorStatus::Ok,
+            },
+            TemperatureRecord {
+                sensor: 2,
+                kind: TemperatureSensorKind::Dimm,
+                value: 0,
+                status: SensorStatus::NotPresent,
+            },
+        ];
+        let mut trailer = [0; 64];
+        let len = encode_records(&records, &mut trailer).unwrap();
+        assert_eq!(
+            format_temperatures(
+                TemperaturesResponseV0::Success,
+                &trailer[..len],
+                Format::Table,
+            ),
+            "response     Success\n\
+             1            Cpu               45.500 C\n\
+             2            Dimm             NotPresent\n",
+        );
+    }
+
+    #[test]
+    fn power_rails_table() {
+        use gimlet_inspector_protocol::records::encode_records;
+
+        let records = [
+            PowerRailRecord {
+                rail: 0,
+                voltage: 12_050,
+                current: 8_250,
+                status: SensorStatus::Ok,
+            },
+            PowerRailRecord {
+                rail: 6,
+                voltage: 880,
+                current: -15,
+                status: SensorStatus::Ok,
+            },
+            PowerRailRecord {
+                rail: 9,
+                voltage: 0,
+                current: 0,
+                status: SensorStatus::DeviceError,
+            },
+        ];
+        let mut trailer = [0; 64];
+        let len = encode_records(&records, &mut trailer).unwrap();
+        assert_eq!(
+            format_power_rails(
+                PowerRailsResponseV0::Success,
+                &trailer[..len],
+                Format::Table,
+            ),
+            "response     Success\n\
+             0             12.050 V   8.250 A\n\
+             6              0.880 V  -0.015 A\n\
+             9            DeviceError\n",
+        );
+    }
+
+    #[test]
+    fn host_power_state_table() {
+        use gimlet_inspector_protocol::power::{
+            HostPowerState, TransitionCause,
+        };
+        use gimlet_inspector_protocol::records::encode_records;
+
+        let history = [
+            PowerTransition {
+                timestamp: 12,
+                from: HostPowerState::A2,
+                to: HostPowerState::A2,
+                cause: TransitionCause::SpBoot,
+            },
+            PowerTransition {
+                timestamp: 30_000,
+                from: HostPowerState::A2,
+                to: HostPowerState::A0,
+                cause: TransitionCause::ControlPlane,
+            },
+        ];
+        let mut trailer = [0; 64];
+        let len = encode_records(&history, &mut trailer).unwrap();
+        assert_eq!(
+            format_host_power_state(
+                HostPowerStateResponseV0::Success {
+                    state: HostPowerState::A0,
+                    now: 45_000,
+                },
+                &trailer[..len],
+                Format::Table,
+            ),
+            "response     Success\n\
+             state        A0\n\
+             now          45000 ms\n\
+             12 ms        A2 -> A2 (SpBoot)\n\
+             30000 ms     A2 -> A0 (ControlPlane)\n",
+        );
+    }
+
+    #[test]
+    fn tasks_table() {
+        use gimlet_inspector_protocol::records::encode_records;
+        use gimlet_inspector_protocol::tasks::{TaskFault, TaskState};
+
+        let tasks = [
+            TaskRecord {
+                index: 0,
+                generation: 0,
+                restarts: 0,
+                state: TaskState::Blocked,
+            },
+            TaskRecord {
+                index: 1,
+                generation: 17,
+                restarts: 529,
+                state: TaskState::Faulted(TaskFault::Panic),
+            },
+        ];
+        let mut trailer = [0; 64];
+        let len = encode_records(&tasks, &mut trailer).unwrap();
+        assert_eq!(
+            format_tasks(
+                TasksResponseV0::Success { total: 2 },
+                &trailer[..len],
+                Format::Table,
+            ),
+            "response     Success\n\
+             tasks        2\n\
+             index        state                     gen restarts\n\
+             0            Blocked                     0        0\n\
+             1            Faulted(Panic)             17      529\n",
+        );
+    }
+
+    #[test]
+    fn fpga_status_table() {
+        use gimlet_inspector_protocol::{FpgaConfigState, FpgaStatusV0};
+
+        assert_eq!(
+            format_fpga_status(
+                FpgaStatusResponseV0::Success(FpgaStatusV0 {
+                    state: FpgaConfigState::LoadFailed,
+                    checksum: 0,
+                    expected_checksum: 0x9abc_def0,
+                    error_code: 0x102,
+                }),
+                &[],
+                Format::Table,
+            ),
+            "response     Success\n\
+             state        LoadFailed\n\
+             checksum     0x00000000\n\
+             expected     0x9abcdef0\n\
+             error code   0x00000102\n",
+        );
+        assert_eq!(
+            format_fpga_status(
+                FpgaStatusResponseV0::FpgaStatusReadFailed,
+                &[],
+                Format::Table,
+            ),
+            "response     FpgaStatusReadFailed\n",
+        );
+    }
+
+    #[test]
+    fn post_codes_table() {
+        use gimlet_inspector_protocol::records::encode_prefixed_records;
+
+        let mut trailer = [0; 16];
+        let len =
+            encode_prefixed_records(&[0xee_u32, 0xdead_beef], &mut trailer)
+                .unwrap();
+        assert_eq!(
+            format_post_codes(
+                PostCodesResponseV0::Success { total: 300 },
+                &trailer[..len],
+                Format::Table,
+            ),
+            "response     Success\n\
+             total        300\n\
+             0x000000ee\n\
+             0xdeadbeef\n",
+        );
+        assert_eq!(
+            format_post_codes(
+                PostCodesResponseV0::NoHostPower,
+                &[],
+                Format::Table,
+            ),
+            "response     NoHostPower\n",
+        );
+    }
+
+    #[test]
+    fn sweep_table() {
+        let sp = |port| SocketAddr::from(([10, 0, 0, 1], port));
+        let success = |value| {
+            let mut dump = vec![0; 64];
+            dump[0x0b] = value;
+            Ok((SequencerRegistersResponseV0::Success, dump))
+        };
+        let sweep = SequencerSweep {
+            results: vec![
+                (sp(1), success(0)),
+                (sp(2), success(0x80)),
+                (sp(3), success(0x40)),
+                (sp(4), success(0)),
+                (
+                    sp(5),
+                    Ok((
+                        SequencerRegistersResponseV0::SequencerTaskDead,
+                        vec![],
+                    )),
+                ),
+                (sp(6), Err(ClientError::Timeout { attempts: 4 })),
+            ],
+        };
+        assert_eq!(
+            format_sweep(&sweep, Format::Table),
+            "sp          response\n\
+             10.0.0.1:1  Success\n\
+             10.0.0.1:2  Success\n\
+             10.0.0.1:3  Success\n\
+             10.0.0.1:4  Success\n\
+             10.0.0.1:5  SequencerTaskDead\n\
+             10.0.0.1:6  error: no reply after 4 attempts\n\
+             \n\
+             offset  common  sp          value\n\
+             0x0b    0x00    10.0.0.1:2  0x80\n\
+             \x20               10.0.0.1:3  0x40\n",
+        );
+    }
+
+    #[test]
+    fn query_mock_agent() {
+        use gimlet_inspector_protocol::mock::MockAgent;
+
+        let agent = MockAgent::start().unwrap();
+        let client = InspectorClient::bind("127.0.0.1:0").unwrap();
+        let out = query_one(
+            &client,
+            agent.addr(),
+            QueryV0::Capabilities,
+            0,
+            Format::Table,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "max version  V1\n\
+             queries      SequencerRegisters, Capabilities, Temperatures, \
+             PowerRails, HostPowerState, Tasks, Identity, FpgaStatus, \
+             PostCodes, Ringbuf\n",
+        );
+
+        // With no trailer, the packet is dumped as received, header and all.
+        let out = query_one(
+            &client,
+            agent.addr(),
+            QueryV0::FpgaStatus,
+            0,
+            Format::Hex,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "0000: fe 01 00 00 00 00 02 00 00 00 00 00 00 00 00 00\n\
+             0010: 00 00 00\n",
+        );
+    }
+}
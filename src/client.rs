@@ -0,0 +1,598 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Blocking UDP client for the inspector agent. Requires the `std` feature.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::query::{self, Query};
+use crate::ringbuf::{RingbufError, RingbufReader};
+use crate::{
+    decode_response, decode_response_v1, encode_request, response_v1_id,
+    CapabilitiesResponseV0, ErrorResponse, FpgaStatusResponseV0, FrameError,
+    HostPowerStateResponseV0, IdentityResponseV0, PostCodesResponseV0,
+    PowerRailsResponseV0, Request, RingbufRequestV0, RingbufResponseV0,
+    SequencerRegistersResponseV0, TasksResponseV0, TemperaturesResponseV0,
+    ANY_RESPONSE_V1_MAX_SIZE, ERROR_RESPONSE_MARKER, REQUEST_MAX_SIZE,
+    RINGBUF_REQ_V0_TRAILER,
+};
+
+/// How long to wait for each reply before retrying, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many times to resend a request that got no reply, by default.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Which version of `Request` a client sends.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RequestVersion {
+    /// Always send `Request::V0`. Every agent understands it, but its replies
+    /// carry no ID, so a late reply to an earlier call can be taken for the
+    /// reply to the current one.
+    V0,
+    /// Always send `Request::V1`, with a fresh ID per call. Agents that predate
+    /// it answer `ErrorResponse::UnsupportedVersion`, or don't answer at all.
+    V1,
+    /// Send `Request::V1`, but if the agent answers `UnsupportedVersion`,
+    /// repeat the call as `Request::V0`. The client remembers which agents did
+    /// so, and sends them only V0 from then on. Agents old enough not to answer
+    /// V1 at all look no different from lost packets, so they need `V0`.
+    #[default]
+    Auto,
+}
+
+/// What `InspectorClient::call_with_packet` returns: the response, its
+/// trailer, and the whole reply packet.
+pub type PacketReply<Q> =
+    Result<(<Q as Query>::Response, Vec<u8>, Vec<u8>), ClientError>;
+
+/// A UDP socket for talking to inspector agents.
+///
+/// The client is not tied to a single agent; each call names the SP it should
+/// be sent to. By default, requests are sent as `Request::V1`, with a fresh ID
+/// per call, so that replies to earlier calls are never mistaken for the
+/// current one; see `RequestVersion` for agents that don't understand V1.
+#[derive(Debug)]
+pub struct InspectorClient {
+    socket: UdpSocket,
+    timeout: Duration,
+    retries: u32,
+    version: RequestVersion,
+    next_id: AtomicU32,
+    /// Agents found by `RequestVersion::Auto` to understand only V0, by
+    /// address in canonical form.
+    v0_agents: Mutex<HashSet<SocketAddr>>,
+}
+
+impl InspectorClient {
+    /// Binds a new client socket to `addr`. Use port 0 to have the OS pick
+    /// one.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            version: RequestVersion::default(),
+            next_id: AtomicU32::new(0),
+            v0_agents: Mutex::default(),
+        })
+    }
+
+    /// Sets how long to wait for each reply before retrying.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times to resend a request that got no reply. Zero means
+    /// the request is sent exactly once.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets which version of `Request` to send.
+    pub fn with_version(mut self, version: RequestVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Returns the local address of the client socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Issues `QueryV0::SequencerRegisters` to the agent at `sp`. On
+    /// `Success`, the returned trailer holds the register dump.
+    pub fn sequencer_registers(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(SequencerRegistersResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::SequencerRegisters>(sp)
+    }
+
+    /// Issues `QueryV0::Capabilities` to the agent at `sp`, to find out which
+    /// queries it supports.
+    ///
+    /// Agents that predate this query reply with
+    /// `ClientError::ErrorResponse(ErrorResponse::UnknownQuery)`, or time out;
+    /// either way, only `sequencer_registers` should be relied on.
+    pub fn capabilities(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<CapabilitiesResponseV0, ClientError> {
+        self.call::<query::Capabilities>(sp)
+            .map(|(response, _trailer)| response)
+    }
+
+    /// Issues `QueryV0::Temperatures` to the agent at `sp`. On `Success`, the
+    /// returned trailer holds the readings, which can be decoded with
+    /// `records::decode_records::<sensors::TemperatureRecord>`.
+    pub fn temperatures(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(TemperaturesResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::Temperatures>(sp)
+    }
+
+    /// Issues `QueryV0::PowerRails` to the agent at `sp`. On `Success`, the
+    /// returned trailer holds the telemetry, which can be decoded with
+    /// `records::decode_records::<sensors::PowerRailRecord>`.
+    pub fn power_rails(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(PowerRailsResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::PowerRails>(sp)
+    }
+
+    /// Issues `QueryV0::HostPowerState` to the agent at `sp`. On `Success`, the
+    /// returned trailer holds the transition history, which can be decoded
+    /// with `records::decode_records::<power::PowerTransition>`.
+    pub fn host_power_state(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(HostPowerStateResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::HostPowerState>(sp)
+    }
+
+    /// Issues `QueryV0::Tasks` to the agent at `sp`. The returned trailer
+    /// holds the task table, which can be decoded with
+    /// `records::decode_records::<tasks::TaskRecord>`.
+    pub fn tasks(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(TasksResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::Tasks>(sp)
+    }
+
+    /// Issues `QueryV0::Identity` to the agent at `sp`.
+    pub fn identity(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<IdentityResponseV0, ClientError> {
+        self.call::<query::Identity>(sp)
+            .map(|(response, _trailer)| response)
+    }
+
+    /// Issues `QueryV0::FpgaStatus` to the agent at `sp`. Worth checking before
+    /// trusting a `sequencer_registers` dump.
+    pub fn fpga_status(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<FpgaStatusResponseV0, ClientError> {
+        self.call::<query::FpgaStatus>(sp)
+            .map(|(response, _trailer)| response)
+    }
+
+    /// Issues `QueryV0::PostCodes` to the agent at `sp`. On `Success`, the
+    /// returned trailer holds the codes, which can be decoded with
+    /// `records::decode_prefixed_records::<u32>`.
+    pub fn post_codes(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(PostCodesResponseV0, Vec<u8>), ClientError> {
+        self.call::<query::PostCodes>(sp)
+    }
+
+    /// Issues `QueryV0::Ringbuf` to the agent at `sp`, asking for the chunk
+    /// described by `request`. On `Success`, the returned trailer holds the
+    /// chunk. Use `read_ringbuf` to get the whole ringbuf.
+    pub fn ringbuf_chunk(
+        &self,
+        sp: SocketAddr,
+        request: RingbufRequestV0,
+    ) -> Result<(RingbufResponseV0, Vec<u8>), ClientError> {
+        let mut trailer = [0; RINGBUF_REQ_V0_TRAILER];
+        let len = hubpack::serialize(&mut trailer, &request)
+            .map_err(FrameError::from)?;
+        self.call_with_trailer::<query::Ringbuf>(sp, &trailer[..len])
+    }
+
+    /// Reads the whole of ringbuf `ringbuf` from the agent at `sp`, one chunk
+    /// at a time.
+    pub fn read_ringbuf(
+        &self,
+        sp: SocketAddr,
+        ringbuf: u16,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut reader = RingbufReader::new(ringbuf);
+        while let Some(request) = reader.next_request() {
+            let (response, chunk) = self.ringbuf_chunk(sp, request)?;
+            reader.push(response, &chunk)?;
+        }
+        Ok(reader.into_data())
+    }
+
+    /// Sends query `Q` to `sp` and waits for its response, retrying on
+    /// timeout. Packets from addresses other than `sp`, and replies to other
+    /// requests, are ignored.
+    ///
+    /// The methods above wrap this for each query; calling it directly is
+    /// useful for code that is itself generic over `Query`.
+    pub fn call<Q: Query>(
+        &self,
+        sp: SocketAddr,
+    ) -> Result<(Q::Response, Vec<u8>), ClientError> {
+        self.call_with_trailer::<Q>(sp, &[])
+    }
+
+    /// Like `call`, for queries that take a request trailer. `trailer` may be
+    /// at most `Q::MAX_TRAILER` bytes.
+    pub fn call_with_trailer<Q: Query>(
+        &self,
+        sp: SocketAddr,
+        trailer: &[u8],
+    ) -> Result<(Q::Response, Vec<u8>), ClientError> {
+        self.call_with_packet::<Q>(sp, trailer)
+            .map(|(response, trailer, _packet)| (response, trailer))
+    }
+
+    /// Like `call_with_trailer`, but also returns the reply packet exactly as
+    /// it was received, including any `ResponseHeaderV1`, for debugging.
+    pub fn call_with_packet<Q: Query>(
+        &self,
+        sp: SocketAddr,
+        trailer: &[u8],
+    ) -> PacketReply<Q> {
+        let auto = self.version == RequestVersion::Auto;
+        let v1 = match self.version {
+            RequestVersion::V0 => false,
+            RequestVersion::V1 => true,
+            RequestVersion::Auto => {
+                !self.v0_agents.lock().unwrap().contains(&canonical(sp))
+            }
+        };
+        match self.send::<Q>(sp, trailer, v1, self.retries + 1) {
+            Err(ClientError::ErrorResponse(
+                ErrorResponse::UnsupportedVersion,
+            )) if auto && v1 => {
+                self.v0_agents.lock().unwrap().insert(canonical(sp));
+                self.send::<Q>(sp, trailer, false, self.retries + 1)
+            }
+            result => result,
+        }
+    }
+
+    /// Sends query `Q` to `sp` as a `Request::V1` if `v1` is set, or as a
+    /// `Request::V0` otherwise, up to `attempts` times.
+    fn send<Q: Query>(
+        &self,
+        sp: SocketAddr,
+        trailer: &[u8],
+        v1: bool,
+        attempts: u32,
+    ) -> PacketReply<Q> {
+        let mut request = [0; REQUEST_MAX_SIZE];
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let query = Q::QUERY;
+        let version = if v1 {
+            Request::V1 { id, query }
+        } else {
+            Request::V0(query)
+        };
+        let len = encode_request(&version, trailer, &mut request)?;
+        let request = &request[..len];
+
+        let mut reply = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        for _ in 0..attempts {
+            self.socket.send_to(request, sp)?;
+            let deadline = Instant::now() + self.timeout;
+            while let Some(remaining) =
+                deadline.checked_duration_since(Instant::now())
+            {
+                // A zero read timeout means "block forever" to std.
+                if remaining.is_zero() {
+                    break;
+                }
+                self.socket.set_read_timeout(Some(remaining))?;
+                let (n, from) = match self.socket.recv_from(&mut reply) {
+                    Ok(r) => r,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if !is_from(sp, from) {
+                    continue;
+                }
+                let reply = &reply[..n];
+                let is_error = reply.first() == Some(&ERROR_RESPONSE_MARKER);
+                let decoded = match (v1, response_v1_id(reply)) {
+                    // A late reply to an earlier request of the other version.
+                    // Bare error responses can answer either.
+                    (true, None) if !is_error => continue,
+                    (false, Some(_)) => continue,
+                    (true, _) => decode_response_v1(id, reply),
+                    (false, None) => decode_response(reply),
+                };
+                return match decoded {
+                    Ok((response, trailer)) => {
+                        Ok((response, trailer.to_vec(), reply.to_vec()))
+                    }
+                    Err(FrameError::WrongId { .. }) => continue,
+                    Err(e) => Err(e.into()),
+                };
+            }
+        }
+        Err(ClientError::Timeout { attempts })
+    }
+}
+
+/// Checks whether a packet received from `from` came from `sp`.
+///
+/// A socket bound to an IPv6 address, such as the CLI's default of `[::]:0`,
+/// sees IPv4 peers as IPv4-mapped IPv6 addresses, so both addresses are put in
+/// canonical form before comparing them.
+pub(crate) fn is_from(sp: SocketAddr, from: SocketAddr) -> bool {
+    canonical(sp) == canonical(from)
+}
+
+/// Converts an IPv4-mapped IPv6 address to plain IPv4, for use as a key.
+pub(crate) fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Reasons a client call can fail.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The socket returned an error.
+    Io(io::Error),
+    /// The request couldn't be encoded, or the reply couldn't be decoded.
+    Frame(FrameError),
+    /// The agent couldn't process the request and said why.
+    ErrorResponse(ErrorResponse),
+    /// No reply arrived after the given number of attempts.
+    Timeout { attempts: u32 },
+    /// A ringbuf couldn't be read in full.
+    Ringbuf(RingbufError),
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<RingbufError> for ClientError {
+    fn from(e: RingbufError) -> Self {
+        Self::Ringbuf(e)
+    }
+}
+
+impl From<FrameError> for ClientError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            FrameError::ErrorResponse(e) => Self::ErrorResponse(e),
+            e => Self::Frame(e),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "socket error: {e}"),
+            Self::Frame(e) => write!(f, "bad packet: {e:?}"),
+            Self::ErrorResponse(e) => write!(f, "agent returned error: {e:?}"),
+            Self::Timeout { attempts } => {
+                write!(f, "no reply after {attempts} attempts")
+            }
+            Self::Ringbuf(e) => write!(f, "can't read ringbuf: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode_request, encode_error_response, encode_response,
+        encode_response_v1, QueryV0, SEQ_REG_RESP_V0_TRAILER,
+    };
+    use std::thread;
+
+    fn client() -> InspectorClient {
+        InspectorClient::bind("127.0.0.1:0")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100))
+            .with_version(RequestVersion::V1)
+    }
+
+    #[test]
+    fn sequencer_registers_success() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sp = agent.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; REQUEST_MAX_SIZE];
+            let (n, from) = agent.recv_from(&mut buf).unwrap();
+            let (request, _) = decode_request(&buf[..n]).unwrap();
+            assert_eq!(request.query(), QueryV0::SequencerRegisters);
+            let id = request.id().unwrap();
+
+            // Late replies to earlier requests, whether V1 or V0, must be
+            // skipped over.
+            let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+            let n = encode_response(
+                &SequencerRegistersResponseV0::SequencerTaskDead,
+                &[],
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], from).unwrap();
+            let n = encode_response_v1(
+                id.wrapping_sub(1),
+                &SequencerRegistersResponseV0::SequencerTaskDead,
+                &[],
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], from).unwrap();
+
+            let n = encode_response_v1(
+                id,
+                &SequencerRegistersResponseV0::Success,
+                &[7; SEQ_REG_RESP_V0_TRAILER],
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], from).unwrap();
+        });
+
+        let (response, trailer) = client().sequencer_registers(sp).unwrap();
+        assert_eq!(response, SequencerRegistersResponseV0::Success);
+        assert_eq!(trailer, [7; SEQ_REG_RESP_V0_TRAILER]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn ipv4_agent_from_ipv6_socket() {
+        let agent = crate::mock::MockAgent::start().unwrap();
+        assert!(agent.addr().is_ipv4());
+        let client = InspectorClient::bind("[::]:0")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100));
+        let (response, _) = client.sequencer_registers(agent.addr()).unwrap();
+        assert_eq!(response, SequencerRegistersResponseV0::Success);
+
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:23547".parse().unwrap();
+        assert!(is_from("127.0.0.1:23547".parse().unwrap(), mapped));
+        assert!(!is_from("127.0.0.1:23548".parse().unwrap(), mapped));
+        assert!(!is_from("127.0.0.2:23547".parse().unwrap(), mapped));
+    }
+
+    #[test]
+    fn v0_only_agent() {
+        let agent = crate::mock::MockAgent::start_v0_only().unwrap();
+        let sp = agent.addr();
+
+        let (response, _) = client()
+            .with_version(RequestVersion::V0)
+            .sequencer_registers(sp)
+            .unwrap();
+        assert_eq!(response, SequencerRegistersResponseV0::Success);
+
+        // Silence is taken for lost packets, not for a V0-only agent.
+        for version in [RequestVersion::V1, RequestVersion::Auto] {
+            let client = client().with_retries(0).with_version(version);
+            match client.sequencer_registers(sp) {
+                Err(ClientError::Timeout { attempts: 1 }) => (),
+                other => panic!("expected timeout, got {other:?}"),
+            }
+        }
+        let query = QueryV0::SequencerRegisters;
+        assert_eq!(
+            agent.received(),
+            [
+                Request::V0(query),
+                Request::V1 { id: 0, query },
+                Request::V1 { id: 0, query },
+            ],
+        );
+    }
+
+    #[test]
+    fn auto_falls_back_on_unsupported_version() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sp = agent.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; REQUEST_MAX_SIZE];
+            let mut out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+            let mut recv = || {
+                let (n, from) = agent.recv_from(&mut buf).unwrap();
+                (decode_request(&buf[..n]).unwrap().0, from)
+            };
+
+            // The first V1 request is lost, and its retry refused.
+            let query = QueryV0::SequencerRegisters;
+            assert_eq!(recv().0, Request::V1 { id: 0, query });
+            let (request, from) = recv();
+            assert_eq!(request, Request::V1 { id: 0, query });
+            let n = encode_error_response(
+                ErrorResponse::UnsupportedVersion,
+                &mut out,
+            )
+            .unwrap();
+            agent.send_to(&out[..n], from).unwrap();
+
+            // Both the fallback and the next call are sent as V0.
+            for _ in 0..2 {
+                let (request, from) = recv();
+                assert_eq!(request, Request::V0(query));
+                let n = encode_response(
+                    &SequencerRegistersResponseV0::SequencerTaskDead,
+                    &[],
+                    &mut out,
+                )
+                .unwrap();
+                agent.send_to(&out[..n], from).unwrap();
+            }
+        });
+
+        let client = client().with_version(RequestVersion::Auto);
+        for _ in 0..2 {
+            let (response, _) = client.sequencer_registers(sp).unwrap();
+            assert_eq!(
+                response,
+                SequencerRegistersResponseV0::SequencerTaskDead
+            );
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn sequencer_registers_timeout() {
+        // Nobody ever answers on this socket.
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sp = agent.local_addr().unwrap();
+
+        match client().with_retries(1).sequencer_registers(sp) {
+            Err(ClientError::Timeout { attempts: 2 }) => (),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+        let mut buf = [0; REQUEST_MAX_SIZE];
+        agent.set_nonblocking(true).unwrap();
+        assert!(agent.recv_from(&mut buf).is_ok());
+        assert!(agent.recv_from(&mut buf).is_ok());
+    }
+}
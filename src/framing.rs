@@ -0,0 +1,343 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Packet framing: a `hubpack`-encoded message followed by an optional binary
+//! trailer.
+//!
+//! Both halves of the protocol should go through these functions rather than
+//! calling `hubpack` directly, so that trailer limits are enforced the same
+//! way everywhere.
+
+use crate::{
+    ErrorResponse, HasTrailer, Request, Response, ResponseHeaderV1,
+    ERROR_RESPONSE_MARKER, RESPONSE_V1_MARKER,
+};
+
+/// Reasons a packet can fail to encode or decode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FrameError {
+    /// The output buffer can't hold the encoded message plus its trailer.
+    BufferTooSmall,
+    /// The trailer is longer than the message it follows allows.
+    TrailerTooLong { max: usize, actual: usize },
+    /// `hubpack` failed to encode or decode the message itself.
+    Hubpack(hubpack::Error),
+    /// The agent sent an `ErrorResponse` instead of the expected response.
+    ErrorResponse(ErrorResponse),
+    /// The response is for a different request than the one expected.
+    WrongId { expected: u32, actual: u32 },
+}
+
+impl From<hubpack::Error> for FrameError {
+    fn from(e: hubpack::Error) -> Self {
+        match e {
+            hubpack::Error::Overrun => Self::BufferTooSmall,
+            e => Self::Hubpack(e),
+        }
+    }
+}
+
+/// Encodes `request` followed by `trailer` into `out`, returning the number of
+/// bytes used. `out` should be at least `REQUEST_MAX_SIZE` bytes.
+pub fn encode_request(
+    request: &Request,
+    trailer: &[u8],
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    check_trailer(request.max_trailer(), trailer)?;
+    encode(request, trailer, out)
+}
+
+/// Decodes a request packet, returning the request and its trailer.
+pub fn decode_request(packet: &[u8]) -> Result<(Request, &[u8]), FrameError> {
+    let (request, trailer) = hubpack::deserialize::<Request>(packet)?;
+    check_trailer(request.max_trailer(), trailer)?;
+    Ok((request, trailer))
+}
+
+/// Encodes `response` followed by `trailer` into `out`, returning the number
+/// of bytes used. `out` should be at least `ANY_RESPONSE_V0_MAX_SIZE` bytes.
+pub fn encode_response<T: Response>(
+    response: &T,
+    trailer: &[u8],
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    check_trailer(response.max_trailer(), trailer)?;
+    encode(response, trailer, out)
+}
+
+/// Encodes an `ErrorResponse` packet into `out`, returning the number of bytes
+/// used. `out` should be at least `ERROR_RESPONSE_SIZE` bytes.
+pub fn encode_error_response(
+    error: ErrorResponse,
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    let (marker, rest) =
+        out.split_first_mut().ok_or(FrameError::BufferTooSmall)?;
+    *marker = ERROR_RESPONSE_MARKER;
+    Ok(1 + hubpack::serialize(rest, &error)?)
+}
+
+/// Decodes a response packet of the type expected for the query that was
+/// sent, returning the response and its trailer.
+///
+/// If the agent sent an `ErrorResponse` instead, it's returned as
+/// `FrameError::ErrorResponse`.
+pub fn decode_response<T: Response>(
+    packet: &[u8],
+) -> Result<(T, &[u8]), FrameError> {
+    if let [ERROR_RESPONSE_MARKER, rest @ ..] = packet {
+        let (error, trailer) = hubpack::deserialize::<ErrorResponse>(rest)?;
+        check_trailer(0, trailer)?;
+        return Err(FrameError::ErrorResponse(error));
+    }
+    let (response, trailer) = hubpack::deserialize::<T>(packet)?;
+    check_trailer(response.max_trailer(), trailer)?;
+    Ok((response, trailer))
+}
+
+/// Encodes `response` followed by `trailer` into `out` as the answer to the
+/// `Request::V1` with the given `id`, returning the number of bytes used. `out`
+/// should be at least `ANY_RESPONSE_V1_MAX_SIZE` bytes.
+pub fn encode_response_v1<T: Response>(
+    id: u32,
+    response: &T,
+    trailer: &[u8],
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    let header = encode_header_v1(id, out)?;
+    Ok(header + encode_response(response, trailer, &mut out[header..])?)
+}
+
+/// Encodes an `ErrorResponse` packet into `out` as the answer to the
+/// `Request::V1` with the given `id`, returning the number of bytes used.
+pub fn encode_error_response_v1(
+    id: u32,
+    error: ErrorResponse,
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    let header = encode_header_v1(id, out)?;
+    Ok(header + encode_error_response(error, &mut out[header..])?)
+}
+
+/// Decodes the response to the `Request::V1` with ID `expected_id`, returning
+/// the response and its trailer.
+///
+/// A response carrying any other ID is rejected with `FrameError::WrongId`;
+/// clients should generally discard it and keep waiting. As with
+/// `decode_response`, an `ErrorResponse` is returned as
+/// `FrameError::ErrorResponse`, whether or not it carries a header.
+pub fn decode_response_v1<T: Response>(
+    expected_id: u32,
+    packet: &[u8],
+) -> Result<(T, &[u8]), FrameError> {
+    match packet {
+        [RESPONSE_V1_MARKER, rest @ ..] => {
+            let (header, body) =
+                hubpack::deserialize::<ResponseHeaderV1>(rest)?;
+            if header.id != expected_id {
+                return Err(FrameError::WrongId {
+                    expected: expected_id,
+                    actual: header.id,
+                });
+            }
+            decode_response(body)
+        }
+        [ERROR_RESPONSE_MARKER, ..] => decode_response(packet),
+        _ => Err(FrameError::Hubpack(hubpack::Error::Invalid)),
+    }
+}
+
+/// Returns the ID from the header of a response to a `Request::V1`, without
+/// decoding the rest of it, or `None` if the packet has no header. This is for
+/// clients with several requests outstanding, to work out which one a packet
+/// answers before they know what type to decode it as.
+pub fn response_v1_id(packet: &[u8]) -> Option<u32> {
+    match packet {
+        [RESPONSE_V1_MARKER, rest @ ..] => {
+            let (header, _) =
+                hubpack::deserialize::<ResponseHeaderV1>(rest).ok()?;
+            Some(header.id)
+        }
+        _ => None,
+    }
+}
+
+fn encode_header_v1(id: u32, out: &mut [u8]) -> Result<usize, FrameError> {
+    let (marker, rest) =
+        out.split_first_mut().ok_or(FrameError::BufferTooSmall)?;
+    *marker = RESPONSE_V1_MARKER;
+    Ok(1 + hubpack::serialize(rest, &ResponseHeaderV1 { id })?)
+}
+
+fn check_trailer(max: usize, trailer: &[u8]) -> Result<(), FrameError> {
+    if trailer.len() > max {
+        return Err(FrameError::TrailerTooLong {
+            max,
+            actual: trailer.len(),
+        });
+    }
+    Ok(())
+}
+
+fn encode(
+    message: &impl serde::Serialize,
+    trailer: &[u8],
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    let len = hubpack::serialize(out, message)?;
+    let end = len + trailer.len();
+    out.get_mut(len..end)
+        .ok_or(FrameError::BufferTooSmall)?
+        .copy_from_slice(trailer);
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn request_round_trip() {
+        let request = Request::V0(QueryV0::SequencerRegisters);
+        let mut buf = [0; REQUEST_MAX_SIZE];
+        let len = encode_request(&request, &[], &mut buf).unwrap();
+        assert_eq!(decode_request(&buf[..len]), Ok((request, &[][..])));
+
+        // `SequencerRegisters` takes no trailer, so one on the wire is
+        // rejected.
+        assert_eq!(
+            decode_request(&[0, 0, 0xff]),
+            Err(FrameError::TrailerTooLong { max: 0, actual: 1 }),
+        );
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let trailer = [0xa5; SEQ_REG_RESP_V0_TRAILER];
+        let mut buf = [0; ANY_RESPONSE_V0_MAX_SIZE];
+        let len = encode_response(
+            &SequencerRegistersResponseV0::Success,
+            &trailer,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(
+            len,
+            SequencerRegistersResponseV0::MAX_SIZE + SEQ_REG_RESP_V0_TRAILER,
+        );
+        assert_eq!(
+            decode_response(&buf[..len]),
+            Ok((SequencerRegistersResponseV0::Success, &trailer[..])),
+        );
+    }
+
+    #[test]
+    fn response_trailer_limits() {
+        let mut buf = [0; ANY_RESPONSE_V0_MAX_SIZE];
+        assert_eq!(
+            encode_response(
+                &SequencerRegistersResponseV0::SequencerTaskDead,
+                &[1],
+                &mut buf,
+            ),
+            Err(FrameError::TrailerTooLong { max: 0, actual: 1 }),
+        );
+        assert_eq!(
+            decode_response::<SequencerRegistersResponseV0>(&[1, 1]),
+            Err(FrameError::TrailerTooLong { max: 0, actual: 1 }),
+        );
+        assert_eq!(
+            encode_response(
+                &SequencerRegistersResponseV0::Success,
+                &[0; SEQ_REG_RESP_V0_TRAILER],
+                &mut buf[..SEQ_REG_RESP_V0_TRAILER],
+            ),
+            Err(FrameError::BufferTooSmall),
+        );
+    }
+
+    #[test]
+    fn error_response_round_trip() {
+        let mut buf = [0; ERROR_RESPONSE_SIZE];
+        let len = encode_error_response(ErrorResponse::UnknownQuery, &mut buf)
+            .unwrap();
+        assert_eq!(
+            decode_response::<SequencerRegistersResponseV0>(&buf[..len]),
+            Err(FrameError::ErrorResponse(ErrorResponse::UnknownQuery)),
+        );
+        assert_eq!(
+            encode_error_response(ErrorResponse::UnknownQuery, &mut buf[..1]),
+            Err(FrameError::BufferTooSmall),
+        );
+    }
+
+    #[test]
+    fn response_v1_round_trip() {
+        let trailer = [0xa5; SEQ_REG_RESP_V0_TRAILER];
+        let mut buf = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let len = encode_response_v1(
+            7,
+            &SequencerRegistersResponseV0::Success,
+            &trailer,
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(
+            len,
+            RESPONSE_V1_HEADER_SIZE
+                + SequencerRegistersResponseV0::MAX_SIZE
+                + SEQ_REG_RESP_V0_TRAILER,
+        );
+        assert_eq!(
+            decode_response_v1(7, &buf[..len]),
+            Ok((SequencerRegistersResponseV0::Success, &trailer[..])),
+        );
+        assert_eq!(
+            decode_response_v1::<SequencerRegistersResponseV0>(8, &buf[..len]),
+            Err(FrameError::WrongId {
+                expected: 8,
+                actual: 7
+            }),
+        );
+
+        // A V0 response is not an acceptable answer to a V1 request.
+        assert_eq!(
+            decode_response_v1::<SequencerRegistersResponseV0>(
+                7,
+                &buf[RESPONSE_V1_HEADER_SIZE..len]
+            ),
+            Err(FrameError::Hubpack(hubpack::Error::Invalid)),
+        );
+    }
+
+    #[test]
+    fn error_response_v1_round_trip() {
+        let mut buf = [0; RESPONSE_V1_HEADER_SIZE + ERROR_RESPONSE_SIZE];
+        let len =
+            encode_error_response_v1(7, ErrorResponse::UnknownQuery, &mut buf)
+                .unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(
+            decode_response_v1::<SequencerRegistersResponseV0>(7, &buf),
+            Err(FrameError::ErrorResponse(ErrorResponse::UnknownQuery)),
+        );
+        assert_eq!(
+            decode_response_v1::<SequencerRegistersResponseV0>(8, &buf),
+            Err(FrameError::WrongId {
+                expected: 8,
+                actual: 7
+            }),
+        );
+        // Bare error responses are accepted too.
+        assert_eq!(
+            decode_response_v1::<SequencerRegistersResponseV0>(
+                7,
+                &buf[RESPONSE_V1_HEADER_SIZE..]
+            ),
+            Err(FrameError::ErrorResponse(ErrorResponse::UnknownQuery)),
+        );
+    }
+}
@@ -0,0 +1,391 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Golden wire-format vectors.
+//!
+//! `testdata/vectors.txt` holds one named packet for every request and
+//! response variant, and is the reference other implementations of the
+//! protocol should test against. The test here regenerates the whole file from
+//! the types in this crate and fails if anything has drifted. After a
+//! deliberate protocol addition, run the tests with `EXPECTORATE=overwrite` to
+//! update the file, and check that the diff only adds lines.
+
+use std::fmt::Write;
+
+use crate::*;
+
+const HEADER: &str = "\
+# Golden wire-format vectors for the Gimlet inspector protocol.
+#
+# Each line is a vector name, a space, and the complete UDP payload in hex,
+# including any trailer. Request IDs in V1 vectors are 0x04030201.
+#
+# This file is generated by the `golden` tests in this crate; see src/golden.rs.
+";
+
+/// Request ID used in every V1 vector.
+const ID: u32 = 0x0403_0201;
+
+/// Example sequencer register dump: every byte set to its own offset. The
+/// protocol treats the dump as opaque bytes, so any pattern will do.
+fn example_seq_regs() -> [u8; SEQ_REG_RESP_V0_TRAILER] {
+    let mut dump = [0; SEQ_REG_RESP_V0_TRAILER];
+    for (i, b) in dump.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    dump
+}
+
+/// Example temperature readings: one of each sensor status.
+fn example_temperatures() -> Vec<u8> {
+    use sensors::{SensorStatus, TemperatureRecord, TemperatureSensorKind};
+
+    let records = [
+        TemperatureRecord {
+            sensor: 1,
+            kind: TemperatureSensorKind::Cpu,
+            value: 45_500,
+            status: SensorStatus::Ok,
+        },
+        TemperatureRecord {
+            sensor: 2,
+            kind: TemperatureSensorKind::Dimm,
+            value: 0,
+            status: SensorStatus::NotPresent,
+        },
+        TemperatureRecord {
+            sensor: 3,
+            kind: TemperatureSensorKind::Nic,
+            value: 0,
+            status: SensorStatus::Unpowered,
+        },
+        TemperatureRecord {
+            sensor: 4,
+            kind: TemperatureSensorKind::Ambient,
+            value: -5_250,
+            status: SensorStatus::DeviceError,
+        },
+    ];
+    let mut buf = [0; TEMPS_RESP_V0_TRAILER];
+    let len = records::encode_records(&records, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Example power rail telemetry: a healthy rail, one that's off, and one whose
+/// regulator couldn't be read.
+fn example_power_rails() -> Vec<u8> {
+    use sensors::{PowerRailRecord, SensorStatus};
+
+    let records = [
+        PowerRailRecord {
+            rail: 0,
+            voltage: 12_050,
+            current: 8_250,
+            status: SensorStatus::Ok,
+        },
+        PowerRailRecord {
+            rail: 4,
+            voltage: 0,
+            current: 0,
+            status: SensorStatus::Unpowered,
+        },
+        PowerRailRecord {
+            rail: 10,
+            voltage: 0,
+            current: 0,
+            status: SensorStatus::DeviceError,
+        },
+    ];
+    let mut buf = [0; POWER_RAILS_RESP_V0_TRAILER];
+    let len = records::encode_records(&records, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Example host power history: boot, power-on, and a thermtrip.
+fn example_power_history() -> Vec<u8> {
+    use power::{HostPowerState, PowerTransition, TransitionCause};
+
+    let records = [
+        PowerTransition {
+            timestamp: 12,
+            from: HostPowerState::A2,
+            to: HostPowerState::A2,
+            cause: TransitionCause::SpBoot,
+        },
+        PowerTransition {
+            timestamp: 30_000,
+            from: HostPowerState::A2,
+            to: HostPowerState::A0,
+            cause: TransitionCause::ControlPlane,
+        },
+        PowerTransition {
+            timestamp: 0x0001_0000_0000,
+            from: HostPowerState::A0,
+            to: HostPowerState::A0Thermtrip,
+            cause: TransitionCause::Thermtrip,
+        },
+    ];
+    let mut buf = [0; HOST_POWER_RESP_V0_TRAILER];
+    let len = records::encode_records(&records, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Example task table: a healthy task, a crash-looping one, and a stopped one.
+fn example_tasks() -> Vec<u8> {
+    use tasks::{TaskFault, TaskRecord, TaskState};
+
+    let records = [
+        TaskRecord {
+            index: 0,
+            generation: 0,
+            restarts: 0,
+            state: TaskState::Blocked,
+        },
+        TaskRecord {
+            index: 1,
+            generation: 17,
+            restarts: 529,
+            state: TaskState::Faulted(TaskFault::Panic),
+        },
+        TaskRecord {
+            index: 2,
+            generation: 0,
+            restarts: 0,
+            state: TaskState::Stopped,
+        },
+    ];
+    let mut buf = [0; TASKS_RESP_V0_TRAILER];
+    let len = records::encode_records(&records, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Example identity, with every field filled in.
+fn example_identity() -> IdentityV0 {
+    let mut identity = IdentityV0 {
+        model: [0; 16],
+        revision: 13,
+        serial: [0; 16],
+        image_version: 0x0102_0304,
+        git_commit: [0; 20],
+        fpga_bitstream: 0xa5c3_0f5a,
+    };
+    identity.model[..11].copy_from_slice(b"913-0000019");
+    identity.serial[..11].copy_from_slice(b"BRM42220036");
+    for (i, b) in identity.git_commit.iter_mut().enumerate() {
+        *b = 0xc0 + i as u8;
+    }
+    identity
+}
+
+/// Builds every vector, in file order.
+fn vectors() -> Vec<(String, Vec<u8>)> {
+    let mut out = Vec::new();
+
+    for query in QueryV0::ALL {
+        let name = kebab(&format!("{query:?}"));
+        let trailer = example_request_trailer(query);
+        out.push((
+            format!("request-v0-{name}"),
+            request(Request::V0(query), &trailer),
+        ));
+        out.push((
+            format!("request-v1-{name}"),
+            request(Request::V1 { id: ID, query }, &trailer),
+        ));
+    }
+
+    let seq_regs = example_seq_regs();
+    for (response, trailer) in [
+        (SequencerRegistersResponseV0::Success, &seq_regs[..]),
+        (SequencerRegistersResponseV0::SequencerTaskDead, &[]),
+        (SequencerRegistersResponseV0::SequencerReadRegsFailed, &[]),
+    ] {
+        responses(&mut out, "sequencer-registers", &response, trailer);
+    }
+
+    responses(
+        &mut out,
+        "capabilities",
+        &CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT),
+        &[],
+    );
+
+    let temps = example_temperatures();
+    for (response, trailer) in [
+        (TemperaturesResponseV0::Success, &temps[..]),
+        (TemperaturesResponseV0::SensorTaskDead, &[]),
+    ] {
+        responses(&mut out, "temperatures", &response, trailer);
+    }
+
+    let rails = example_power_rails();
+    for (response, trailer) in [
+        (PowerRailsResponseV0::Success, &rails[..]),
+        (PowerRailsResponseV0::PowerTaskDead, &[]),
+    ] {
+        responses(&mut out, "power-rails", &response, trailer);
+    }
+
+    let history = example_power_history();
+    for (response, trailer) in [
+        (
+            HostPowerStateResponseV0::Success {
+                state: power::HostPowerState::A0Thermtrip,
+                now: 0x0001_0000_1000,
+            },
+            &history[..],
+        ),
+        (HostPowerStateResponseV0::SequencerTaskDead, &[]),
+    ] {
+        responses(&mut out, "host-power-state", &response, trailer);
+    }
+
+    responses(
+        &mut out,
+        "tasks",
+        &TasksResponseV0::Success { total: 3 },
+        &example_tasks(),
+    );
+
+    responses(
+        &mut out,
+        "identity",
+        &IdentityResponseV0::Success(example_identity()),
+        &[],
+    );
+
+    for response in [
+        FpgaStatusResponseV0::Success(FpgaStatusV0 {
+            state: FpgaConfigState::LoadFailed,
+            checksum: 0x1234_5678,
+            expected_checksum: 0x9abc_def0,
+            error_code: 0x0000_0102,
+        }),
+        FpgaStatusResponseV0::SequencerTaskDead,
+        FpgaStatusResponseV0::FpgaStatusReadFailed,
+    ] {
+        responses(&mut out, "fpga-status", &response, &[]);
+    }
+
+    let mut codes = [0; POST_CODES_RESP_V0_TRAILER];
+    let len = records::encode_prefixed_records(
+        &[0x0000_00ee_u32, 0x1234_5678, 0xdead_beef],
+        &mut codes,
+    )
+    .unwrap();
+    for (response, trailer) in [
+        (PostCodesResponseV0::Success { total: 300 }, &codes[..len]),
+        (PostCodesResponseV0::NoHostPower, &[]),
+        (PostCodesResponseV0::BufferUnavailable, &[]),
+    ] {
+        responses(&mut out, "post-codes", &response, trailer);
+    }
+
+    // The chunk answers the example request, which starts at 0x0304_0506, and
+    // the ringbuf carries on past it.
+    let chunk: Vec<u8> = (0..16).collect();
+    for (response, trailer) in [
+        (
+            RingbufResponseV0::Success {
+                total: 0x0304_0600,
+                next: Some(0x0304_0516),
+            },
+            &chunk[..],
+        ),
+        (RingbufResponseV0::NoSuchRingbuf, &[]),
+        (RingbufResponseV0::BadOffset, &[]),
+        (RingbufResponseV0::ReadFailed, &[]),
+    ] {
+        responses(&mut out, "ringbuf", &response, trailer);
+    }
+
+    for error in [
+        ErrorResponse::UnsupportedVersion,
+        ErrorResponse::UnknownQuery,
+        ErrorResponse::MalformedRequest,
+    ] {
+        let name = kebab(&format!("{error:?}"));
+        let mut buf = [0; RESPONSE_V1_HEADER_SIZE + ERROR_RESPONSE_SIZE];
+        let len = encode_error_response(error, &mut buf).unwrap();
+        out.push((format!("error-{name}"), buf[..len].to_vec()));
+        let len = encode_error_response_v1(ID, error, &mut buf).unwrap();
+        out.push((format!("error-v1-{name}"), buf[..len].to_vec()));
+    }
+
+    out
+}
+
+/// Returns an example trailer for a request for `query`, which is empty unless
+/// the query takes one.
+fn example_request_trailer(query: QueryV0) -> Vec<u8> {
+    let mut buf = [0; QUERY_V0_TRAILER];
+    let len = match query {
+        QueryV0::Ringbuf => {
+            let request = RingbufRequestV0 {
+                ringbuf: 0x0102,
+                offset: 0x0304_0506,
+            };
+            hubpack::serialize(&mut buf, &request).unwrap()
+        }
+        _ => 0,
+    };
+    buf[..len].to_vec()
+}
+
+fn request(request: Request, trailer: &[u8]) -> Vec<u8> {
+    let mut buf = [0; REQUEST_MAX_SIZE];
+    let len = encode_request(&request, trailer, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Adds V0 and V1 vectors for a response to `query`, named after the
+/// response's variant.
+fn responses<T: Response + core::fmt::Debug>(
+    out: &mut Vec<(String, Vec<u8>)>,
+    query: &str,
+    response: &T,
+    trailer: &[u8],
+) {
+    let variant = format!("{response:?}");
+    let variant = kebab(variant.split(['(', ' ']).next().unwrap());
+    let mut buf = [0; ANY_RESPONSE_V1_MAX_SIZE];
+
+    let len = encode_response(response, trailer, &mut buf).unwrap();
+    out.push((
+        format!("response-v0-{query}-{variant}"),
+        buf[..len].to_vec(),
+    ));
+    let len = encode_response_v1(ID, response, trailer, &mut buf).unwrap();
+    out.push((
+        format!("response-v1-{query}-{variant}"),
+        buf[..len].to_vec(),
+    ));
+}
+
+/// Converts a `CamelCase` identifier to `kebab-case`.
+fn kebab(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('-');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+#[test]
+fn golden_vectors() {
+    let mut contents = String::from(HEADER);
+    let mut names = std::collections::BTreeSet::new();
+    for (name, bytes) in vectors() {
+        assert!(names.insert(name.clone()), "duplicate vector {name}");
+        write!(contents, "\n{name} ").unwrap();
+        for b in bytes {
+            write!(contents, "{b:02x}").unwrap();
+        }
+    }
+    contents.push('\n');
+    expectorate::assert_contents("testdata/vectors.txt", &contents);
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 //! Gimlet Inspector protocol definition.
 //!
@@ -11,11 +11,44 @@
 //! Gimlet use the `*Response` types -- a response structure may be specific to
 //! the request. In both cases, certain requests/responses may append binary
 //! data _after_ the `hubpack`-encoded data. This is documented below on the
-//! specific items.
+//! specific items. Use `encode_request`, `decode_response`, and friends to
+//! build and split such packets.
+//!
+//! The wire index of every enum variant is recorded in `testdata/variants.txt`,
+//! and the tests fail if a variant is reordered or removed.
 
 use hubpack::SerializedSize;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "async")]
+pub mod async_client;
+#[cfg(any(test, feature = "std"))]
+pub mod client;
+mod framing;
+#[cfg(test)]
+mod golden;
+#[cfg(any(test, feature = "std"))]
+pub mod mock;
+pub mod power;
+pub mod query;
+pub mod records;
+#[cfg(any(test, feature = "std"))]
+pub mod ringbuf;
+pub mod sensors;
+pub mod server;
+#[cfg(feature = "async")]
+pub mod sweep;
+pub mod tasks;
+#[cfg(test)]
+mod variants;
+
+pub use framing::{
+    decode_request, decode_response, decode_response_v1, encode_error_response,
+    encode_error_response_v1, encode_request, encode_response,
+    encode_response_v1, response_v1_id, FrameError,
+};
+
 /// Request format to the inspector agent.
 ///
 /// This is an enum so that we implicitly get a protocol version field at the
@@ -27,16 +60,53 @@ use serde::{Deserialize, Serialize};
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
 )]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
 pub enum Request {
     /// A request in v0 consists only of the name of the query to be issued.
     V0(QueryV0),
+
+    /// A request in v1 adds a client-chosen ID to the v0 query. The agent
+    /// echoes the ID in a `ResponseHeaderV1` at the start of its response, so
+    /// that clients retrying over a lossy network can tell a late reply to an
+    /// earlier request from the reply to the current one.
+    V1 { id: u32, query: QueryV0 },
+}
+
+impl Request {
+    /// Returns the query being issued.
+    pub fn query(&self) -> QueryV0 {
+        match self {
+            Self::V0(query) | Self::V1 { query, .. } => *query,
+        }
+    }
+
+    /// Returns the request ID, if this version of request carries one.
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Self::V0(_) => None,
+            Self::V1 { id, .. } => Some(*id),
+        }
+    }
+}
+
+impl HasTrailer for Request {
+    /// Every version carries a `QueryV0`, so this is the largest query
+    /// trailer.
+    const MAX_TRAILER: usize = QueryV0::MAX_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        self.query().max_trailer()
+    }
 }
 
-/// Maximum trailer size for any defined `Request`. In the event that we start
-/// using request trailers, we'll want to compute this somehow.
-pub const REQUEST_TRAILER: usize = QUERY_V0_TRAILER;
+/// Maximum trailer size for any defined `Request`.
+pub const REQUEST_TRAILER: usize = Request::MAX_TRAILER;
+
+/// Maximum size of any possible request packet, including its trailer. This
+/// is the buffer size the agent should receive into.
+pub const REQUEST_MAX_SIZE: usize = Request::MAX_SIZE + REQUEST_TRAILER;
 
-/// Queries that can be sent in V0. Don't send this raw, use `Request`.
+/// Queries that can be sent in V0 and V1. Don't send this raw, use `Request`.
 ///
 /// The order and presence of variants in this enum _is_ the protocol
 /// definition; do not reorder or remove variants. You can add new variants at
@@ -44,20 +114,151 @@ pub const REQUEST_TRAILER: usize = QUERY_V0_TRAILER;
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
 )]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
 pub enum QueryV0 {
     /// Asks the agent to interrogate the sequencer FPGA and send the register
     /// contents back. The response is always a `SequencerRegistersResponseV0`.
     SequencerRegisters,
+
+    /// Asks the agent which request versions and queries it supports. The
+    /// response is always a `CapabilitiesResponseV0`. Agents that predate this
+    /// query answer with `ErrorResponse::UnknownQuery` or not at all; clients
+    /// should assume such agents support only `SequencerRegisters`.
+    Capabilities,
+
+    /// Asks the agent for the SP's current temperature sensor readings. The
+    /// response is always a `TemperaturesResponseV0`.
+    Temperatures,
+
+    /// Asks the agent for voltage and current telemetry from the main power
+    /// rails, for correlating with the fault bits in a `SequencerRegisters`
+    /// dump. The response is always a `PowerRailsResponseV0`.
+    PowerRails,
+
+    /// Asks the agent for the current host power state and a history of recent
+    /// transitions. The response is always a `HostPowerStateResponseV0`.
+    HostPowerState,
+
+    /// Asks the agent for the status of every task in the SP image, to spot
+    /// crashed or crash-looping tasks. The response is always a
+    /// `TasksResponseV0`.
+    Tasks,
+
+    /// Asks the agent what hardware it's running on and what software the SP
+    /// is running. The response is always an `IdentityResponseV0`.
+    Identity,
+
+    /// Asks the agent whether the sequencer FPGA's bitstream loaded, and if
+    /// not, why. The response is always an `FpgaStatusResponseV0`.
+    FpgaStatus,
+
+    /// Asks the agent for the most recent POST codes the host has emitted, to
+    /// see how far it got in booting. The response is always a
+    /// `PostCodesResponseV0`.
+    PostCodes,
+
+    /// Asks the agent for one chunk of a task's ringbuf. This is the only
+    /// query that takes a trailer: a `RingbufRequestV0` saying which ringbuf
+    /// and where to start. The response is always a `RingbufResponseV0`.
+    Ringbuf,
+}
+
+impl QueryV0 {
+    /// Every query, in protocol order: `ALL[i]` is encoded as `i`.
+    pub const ALL: [Self; 10] = [
+        Self::SequencerRegisters,
+        Self::Capabilities,
+        Self::Temperatures,
+        Self::PowerRails,
+        Self::HostPowerState,
+        Self::Tasks,
+        Self::Identity,
+        Self::FpgaStatus,
+        Self::PostCodes,
+        Self::Ringbuf,
+    ];
+
+    /// Maximum number of trailer bytes that may follow a request for this
+    /// query. This is a `const fn` so that `MAX_TRAILER` can be computed from
+    /// it; the match is exhaustive so that every new query has to say.
+    pub(crate) const fn request_trailer(self) -> usize {
+        match self {
+            Self::Ringbuf => RINGBUF_REQ_V0_TRAILER,
+            Self::SequencerRegisters
+            | Self::Capabilities
+            | Self::Temperatures
+            | Self::PowerRails
+            | Self::HostPowerState
+            | Self::Tasks
+            | Self::Identity
+            | Self::FpgaStatus
+            | Self::PostCodes => 0,
+        }
+    }
+
+    /// Maximum size of the response to this query, including its trailer.
+    pub const fn max_response_size(self) -> usize {
+        match self {
+            Self::SequencerRegisters => {
+                max_size::<SequencerRegistersResponseV0>()
+            }
+            Self::Capabilities => max_size::<CapabilitiesResponseV0>(),
+            Self::Temperatures => max_size::<TemperaturesResponseV0>(),
+            Self::PowerRails => max_size::<PowerRailsResponseV0>(),
+            Self::HostPowerState => max_size::<HostPowerStateResponseV0>(),
+            Self::Tasks => max_size::<TasksResponseV0>(),
+            Self::Identity => max_size::<IdentityResponseV0>(),
+            Self::FpgaStatus => max_size::<FpgaStatusResponseV0>(),
+            Self::PostCodes => max_size::<PostCodesResponseV0>(),
+            Self::Ringbuf => max_size::<RingbufResponseV0>(),
+        }
+    }
+}
+
+impl HasTrailer for QueryV0 {
+    const MAX_TRAILER: usize = {
+        let mut max = 0;
+        let mut i = 0;
+        while i < Self::ALL.len() {
+            let trailer = Self::ALL[i].request_trailer();
+            if trailer > max {
+                max = trailer;
+            }
+            i += 1;
+        }
+        max
+    };
+
+    fn max_trailer(&self) -> usize {
+        self.request_trailer()
+    }
 }
 
 /// Maximum trailer size for any `QueryV0`.
-pub const QUERY_V0_TRAILER: usize = 0;
+pub const QUERY_V0_TRAILER: usize = QueryV0::MAX_TRAILER;
 
 /// Maximum size of any possible response in protocol V0. Clients should know
 /// what response to expect, and don't need to use this constant -- it's
 /// intended for servers.
-pub const ANY_RESPONSE_V0_MAX_SIZE: usize =
-    SequencerRegistersResponseV0::MAX_SIZE + SEQ_REG_RESP_V0_TRAILER;
+pub const ANY_RESPONSE_V0_MAX_SIZE: usize = {
+    let mut max = 0;
+    let mut i = 0;
+    while i < QueryV0::ALL.len() {
+        let size = QueryV0::ALL[i].max_response_size();
+        if size > max {
+            max = size;
+        }
+        i += 1;
+    }
+    max
+};
+
+/// Maximum size of any possible response in protocol V1, which is a V0
+/// response behind a `ResponseHeaderV1`. Servers that accept V1 requests
+/// should size their transmit buffers with this instead of
+/// `ANY_RESPONSE_V0_MAX_SIZE`.
+pub const ANY_RESPONSE_V1_MAX_SIZE: usize =
+    RESPONSE_V1_HEADER_SIZE + ANY_RESPONSE_V0_MAX_SIZE;
 
 /// Response sent in response to `QueryV0::SequencerRegisters`. The variants in
 /// this enum _are_ the protocol definition. Add variants only at the end, and
@@ -66,6 +267,7 @@ pub const ANY_RESPONSE_V0_MAX_SIZE: usize =
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
 )]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
 pub enum SequencerRegistersResponseV0 {
     /// The agent successfully contacted the sequencer and collected its
     /// registers. They are appended in the binary payload section of the
@@ -84,44 +286,824 @@ pub enum SequencerRegistersResponseV0 {
     SequencerReadRegsFailed,
 }
 
+impl HasTrailer for SequencerRegistersResponseV0 {
+    const MAX_TRAILER: usize = SEQ_REG_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success => SEQ_REG_RESP_V0_TRAILER,
+            Self::SequencerTaskDead | Self::SequencerReadRegsFailed => 0,
+        }
+    }
+}
+
+impl Response for SequencerRegistersResponseV0 {}
+
 /// Current limit on "trailer" bytes following a SequencerRegistersResponseV0.
 /// Allocate this much space beyond the hubpack suggested size.
 pub const SEQ_REG_RESP_V0_TRAILER: usize = 64;
 
+/// Response sent in response to `QueryV0::Capabilities`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum CapabilitiesResponseV0 {
+    /// The agent's capabilities. No data is attached.
+    Success(CapabilitiesV0),
+}
+
+impl HasTrailer for CapabilitiesResponseV0 {
+    const MAX_TRAILER: usize = 0;
+
+    fn max_trailer(&self) -> usize {
+        0
+    }
+}
+
+impl Response for CapabilitiesResponseV0 {}
+
+/// What an agent supports, as reported in `CapabilitiesResponseV0`.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct CapabilitiesV0 {
+    /// Index of the highest `Request` variant the agent understands. Agents
+    /// understand every lower version as well.
+    pub max_request_version: u8,
+    /// Bitmap of supported `QueryV0` variants: the query encoded as `i` is
+    /// supported if bit `i % 8` of byte `i / 8` is set. This covers every
+    /// possible query index, so it never needs to grow.
+    pub queries: [u8; 32],
+}
+
+impl CapabilitiesV0 {
+    /// Capabilities of an agent that implements everything in this version of
+    /// the crate.
+    pub const CURRENT: Self = {
+        let mut caps = Self {
+            max_request_version: MAX_REQUEST_VERSION,
+            queries: [0; 32],
+        };
+        let mut i = 0;
+        while i < QueryV0::ALL.len() {
+            caps.queries[i / 8] |= 1 << (i % 8);
+            i += 1;
+        }
+        caps
+    };
+
+    /// Checks whether `query` is marked as supported.
+    pub fn supports(&self, query: QueryV0) -> bool {
+        let i = query as usize;
+        self.queries[i / 8] & (1 << (i % 8)) != 0
+    }
+}
+
+/// Index of the highest `Request` variant defined by this crate.
+pub const MAX_REQUEST_VERSION: u8 = 1;
+
+/// Response sent in response to `QueryV0::Temperatures`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TemperaturesResponseV0 {
+    /// The agent collected the current readings from the sensor task. They are
+    /// appended in the binary payload section of the message as
+    /// `sensors::TemperatureRecord`s, one per sensor, in the format described
+    /// in `records`. At most `TEMPS_RESP_V0_MAX_SENSORS` are sent.
+    Success,
+
+    /// The agent was unable to contact the sensor task because it crashed
+    /// during the attempt. No data is attached.
+    SensorTaskDead,
+}
+
+impl HasTrailer for TemperaturesResponseV0 {
+    const MAX_TRAILER: usize = TEMPS_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success => TEMPS_RESP_V0_TRAILER,
+            Self::SensorTaskDead => 0,
+        }
+    }
+}
+
+impl Response for TemperaturesResponseV0 {}
+
+/// Maximum number of records following a `TemperaturesResponseV0::Success`.
+pub const TEMPS_RESP_V0_MAX_SENSORS: usize = 64;
+
+/// Current limit on "trailer" bytes following a TemperaturesResponseV0.
+/// Allocate this much space beyond the hubpack suggested size.
+pub const TEMPS_RESP_V0_TRAILER: usize =
+    TEMPS_RESP_V0_MAX_SENSORS * sensors::TemperatureRecord::MAX_SIZE;
+
+/// Response sent in response to `QueryV0::PowerRails`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum PowerRailsResponseV0 {
+    /// The agent collected telemetry from the power task. It is appended in
+    /// the binary payload section of the message as
+    /// `sensors::PowerRailRecord`s, one per rail, in the format described in
+    /// `records`. At most `POWER_RAILS_RESP_V0_MAX_RAILS` are sent. Rails that
+    /// couldn't be read are still included, with a status saying why.
+    Success,
+
+    /// The agent was unable to contact the power task because it crashed
+    /// during the attempt. No data is attached.
+    PowerTaskDead,
+}
+
+impl HasTrailer for PowerRailsResponseV0 {
+    const MAX_TRAILER: usize = POWER_RAILS_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success => POWER_RAILS_RESP_V0_TRAILER,
+            Self::PowerTaskDead => 0,
+        }
+    }
+}
+
+impl Response for PowerRailsResponseV0 {}
+
+/// Maximum number of records following a `PowerRailsResponseV0::Success`.
+pub const POWER_RAILS_RESP_V0_MAX_RAILS: usize = 32;
+
+/// Current limit on "trailer" bytes following a PowerRailsResponseV0.
+/// Allocate this much space beyond the hubpack suggested size.
+pub const POWER_RAILS_RESP_V0_TRAILER: usize =
+    POWER_RAILS_RESP_V0_MAX_RAILS * sensors::PowerRailRecord::MAX_SIZE;
+
+/// Response sent in response to `QueryV0::HostPowerState`. The variants in
+/// this enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum HostPowerStateResponseV0 {
+    /// The agent got the power state and history from the sequencer task.
+    /// The most recent transitions are appended in the binary payload section
+    /// of the message as `power::PowerTransition`s, oldest first, in the
+    /// format described in `records`. The sequencer task keeps them in a ring,
+    /// so at most `HOST_POWER_RESP_V0_MAX_TRANSITIONS` are sent and older ones
+    /// are lost.
+    Success {
+        /// The current host power state.
+        state: power::HostPowerState,
+        /// Milliseconds since the SP booted, on the same clock as
+        /// `PowerTransition::timestamp`.
+        now: u64,
+    },
+
+    /// The agent was unable to contact the sequencer task because it crashed
+    /// during the attempt. No data is attached.
+    SequencerTaskDead,
+}
+
+impl HasTrailer for HostPowerStateResponseV0 {
+    const MAX_TRAILER: usize = HOST_POWER_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success { .. } => HOST_POWER_RESP_V0_TRAILER,
+            Self::SequencerTaskDead => 0,
+        }
+    }
+}
+
+impl Response for HostPowerStateResponseV0 {}
+
+/// Maximum number of records following a `HostPowerStateResponseV0::Success`.
+pub const HOST_POWER_RESP_V0_MAX_TRANSITIONS: usize = 16;
+
+/// Current limit on "trailer" bytes following a HostPowerStateResponseV0.
+/// Allocate this much space beyond the hubpack suggested size.
+pub const HOST_POWER_RESP_V0_TRAILER: usize =
+    HOST_POWER_RESP_V0_MAX_TRANSITIONS * power::PowerTransition::MAX_SIZE;
+
+/// Response sent in response to `QueryV0::Tasks`. The variants in this enum
+/// _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TasksResponseV0 {
+    /// The agent read the task table. It is appended in the binary payload
+    /// section of the message as `tasks::TaskRecord`s, in task index order, in
+    /// the format described in `records`. At most `TASKS_RESP_V0_MAX_TASKS`
+    /// are sent.
+    Success {
+        /// Number of tasks in the image. If this is more than the number of
+        /// records attached, the table was cut short.
+        total: u16,
+    },
+}
+
+impl HasTrailer for TasksResponseV0 {
+    const MAX_TRAILER: usize = TASKS_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success { .. } => TASKS_RESP_V0_TRAILER,
+        }
+    }
+}
+
+impl Response for TasksResponseV0 {}
+
+/// Maximum number of records following a `TasksResponseV0::Success`.
+pub const TASKS_RESP_V0_MAX_TASKS: usize = 64;
+
+/// Current limit on "trailer" bytes following a TasksResponseV0. Allocate
+/// this much space beyond the hubpack suggested size.
+pub const TASKS_RESP_V0_TRAILER: usize =
+    TASKS_RESP_V0_MAX_TASKS * tasks::TaskRecord::MAX_SIZE;
+
+/// Response sent in response to `QueryV0::Identity`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum IdentityResponseV0 {
+    /// The SP's identity. No data is attached.
+    Success(IdentityV0),
+}
+
+impl HasTrailer for IdentityResponseV0 {
+    const MAX_TRAILER: usize = 0;
+
+    fn max_trailer(&self) -> usize {
+        0
+    }
+}
+
+impl Response for IdentityResponseV0 {}
+
+/// Hardware and software identity of an SP, as reported in
+/// `IdentityResponseV0`.
+///
+/// This is a fixed 64-byte structure; the fields are encoded in order with no
+/// padding between them:
+///
+/// | Offset | Size | Field                                 |
+/// |--------|------|---------------------------------------|
+/// | 0      | 16   | `model`                               |
+/// | 16     | 4    | `revision`, little-endian `u32`       |
+/// | 20     | 16   | `serial`                              |
+/// | 36     | 4    | `image_version`, little-endian `u32`  |
+/// | 40     | 20   | `git_commit`                          |
+/// | 60     | 4    | `fpga_bitstream`, little-endian `u32` |
+///
+/// The string fields are ASCII, padded with NUL bytes; use `model_str` and
+/// `serial_str` to get at them. A field the SP couldn't read, such as the
+/// serial number when the VPD EEPROM is unprogrammed, is all zeros.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct IdentityV0 {
+    /// Board part number from the VPD, e.g. `913-0000019`.
+    pub model: [u8; 16],
+    /// Board revision from the VPD.
+    pub revision: u32,
+    /// Board serial number from the VPD.
+    pub serial: [u8; 16],
+    /// Version of the running Hubris image.
+    pub image_version: u32,
+    /// Git commit hash the running Hubris image was built from.
+    pub git_commit: [u8; 20],
+    /// ID of the sequencer FPGA bitstream the SP loaded.
+    pub fpga_bitstream: u32,
+}
+
+impl IdentityV0 {
+    /// Returns `model` without its padding, or `None` if it isn't valid UTF-8.
+    pub fn model_str(&self) -> Option<&str> {
+        unpad(&self.model)
+    }
+
+    /// Returns `serial` without its padding, or `None` if it isn't valid
+    /// UTF-8.
+    pub fn serial_str(&self) -> Option<&str> {
+        unpad(&self.serial)
+    }
+}
+
+/// Response sent in response to `QueryV0::FpgaStatus`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum FpgaStatusResponseV0 {
+    /// The agent got the FPGA's configuration status from the sequencer task.
+    /// No data is attached.
+    Success(FpgaStatusV0),
+
+    /// The agent was unable to contact the sequencer task because it crashed
+    /// during the attempt. No data is attached.
+    SequencerTaskDead,
+
+    /// The sequencer task was unable to read the FPGA's configuration status.
+    /// No data is attached.
+    FpgaStatusReadFailed,
+}
+
+impl HasTrailer for FpgaStatusResponseV0 {
+    const MAX_TRAILER: usize = 0;
+
+    fn max_trailer(&self) -> usize {
+        0
+    }
+}
+
+impl Response for FpgaStatusResponseV0 {}
+
+/// Configuration status of the sequencer FPGA, as reported in
+/// `FpgaStatusResponseV0`.
+///
+/// This is a fixed 13-byte structure; the fields are encoded in order with no
+/// padding between them:
+///
+/// | Offset | Size | Field                                    |
+/// |--------|------|------------------------------------------|
+/// | 0      | 1    | `state`, as an `FpgaConfigState` index   |
+/// | 1      | 4    | `checksum`, little-endian `u32`          |
+/// | 5      | 4    | `expected_checksum`, little-endian `u32` |
+/// | 9      | 4    | `error_code`, little-endian `u32`        |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct FpgaStatusV0 {
+    /// Where the FPGA is in loading its bitstream.
+    pub state: FpgaConfigState,
+    /// Checksum of the bitstream the FPGA reports having loaded. Only
+    /// meaningful if `state` is `FpgaConfigState::Configured`.
+    pub checksum: u32,
+    /// Checksum of the bitstream in the SP image. If this differs from
+    /// `checksum`, the FPGA is running a different bitstream than intended.
+    pub expected_checksum: u32,
+    /// Error code from the most recent failed load, or 0 if the last load
+    /// succeeded. The values are specific to the FPGA driver.
+    pub error_code: u32,
+}
+
+/// Configuration states of the sequencer FPGA. The order and presence of the
+/// variants is part of the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum FpgaConfigState {
+    /// The SP hasn't tried to load a bitstream yet.
+    Unconfigured,
+    /// The SP is loading the bitstream.
+    Configuring,
+    /// The bitstream loaded and the FPGA is running it. Only in this state is
+    /// a `SequencerRegisters` dump meaningful.
+    Configured,
+    /// The bitstream failed to load; see `FpgaStatusV0::error_code`.
+    LoadFailed,
+}
+
+/// Response sent in response to `QueryV0::PostCodes`. The variants in this
+/// enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum PostCodesResponseV0 {
+    /// The agent read the POST code buffer. The most recent codes are appended
+    /// in the binary payload section of the message, oldest first, as a
+    /// length-prefixed list of little-endian `u32`s as described in
+    /// `records`. At most `POST_CODES_RESP_V0_MAX_CODES` are sent. An empty
+    /// list means the host is powered but hasn't emitted any codes.
+    Success {
+        /// Number of codes the host has emitted since it was last powered on.
+        /// If this is more than the number attached, older codes have been
+        /// overwritten.
+        total: u32,
+    },
+
+    /// The host is not powered, so there are no POST codes to report. No data
+    /// is attached.
+    NoHostPower,
+
+    /// The agent couldn't get at the POST code buffer, for instance because
+    /// the task that collects the codes crashed. No data is attached.
+    BufferUnavailable,
+}
+
+impl HasTrailer for PostCodesResponseV0 {
+    const MAX_TRAILER: usize = POST_CODES_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success { .. } => POST_CODES_RESP_V0_TRAILER,
+            Self::NoHostPower | Self::BufferUnavailable => 0,
+        }
+    }
+}
+
+impl Response for PostCodesResponseV0 {}
+
+/// Maximum number of codes following a `PostCodesResponseV0::Success`.
+pub const POST_CODES_RESP_V0_MAX_CODES: usize = 128;
+
+/// Current limit on "trailer" bytes following a PostCodesResponseV0.
+/// Allocate this much space beyond the hubpack suggested size.
+pub const POST_CODES_RESP_V0_TRAILER: usize =
+    records::PREFIX_LEN + POST_CODES_RESP_V0_MAX_CODES * u32::MAX_SIZE;
+
+/// Trailer of a `QueryV0::Ringbuf` request, selecting the chunk to send.
+///
+/// This is a fixed 6-byte structure:
+///
+/// | Offset | Size | Field                          |
+/// |--------|------|--------------------------------|
+/// | 0      | 2    | `ringbuf`, little-endian `u16` |
+/// | 2      | 4    | `offset`, little-endian `u32`  |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct RingbufRequestV0 {
+    /// Index of the ringbuf in the SP image's list of ringbufs. Names aren't
+    /// sent; look them up in the image's archive, as Humility does.
+    pub ringbuf: u16,
+    /// Offset into the ringbuf of the first byte to send. Start at 0, then use
+    /// the `next` cursor from each response.
+    pub offset: u32,
+}
+
+/// Size of the trailer of a `QueryV0::Ringbuf` request.
+pub const RINGBUF_REQ_V0_TRAILER: usize = RingbufRequestV0::MAX_SIZE;
+
+/// Response sent in response to `QueryV0::Ringbuf`. The variants in this enum
+/// _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum RingbufResponseV0 {
+    /// The agent read a chunk of the ringbuf, starting at the requested
+    /// offset. The raw ringbuf memory is appended in the binary payload
+    /// section of the message, up to `RINGBUF_RESP_V0_TRAILER` bytes.
+    /// `ringbuf::RingbufReader` puts the chunks back together.
+    Success {
+        /// Size of the whole ringbuf, in bytes.
+        total: u32,
+        /// Offset to request next, or `None` if this chunk reaches the end of
+        /// the ringbuf.
+        next: Option<u32>,
+    },
+
+    /// The SP image has no ringbuf with the requested index. No data is
+    /// attached.
+    NoSuchRingbuf,
+
+    /// The requested offset is past the end of the ringbuf. No data is
+    /// attached.
+    BadOffset,
+
+    /// The agent was unable to read the ringbuf from the memory of the task
+    /// that owns it. No data is attached.
+    ReadFailed,
+}
+
+impl HasTrailer for RingbufResponseV0 {
+    const MAX_TRAILER: usize = RINGBUF_RESP_V0_TRAILER;
+
+    fn max_trailer(&self) -> usize {
+        match self {
+            Self::Success { .. } => RINGBUF_RESP_V0_TRAILER,
+            Self::NoSuchRingbuf | Self::BadOffset | Self::ReadFailed => 0,
+        }
+    }
+}
+
+impl Response for RingbufResponseV0 {}
+
+/// Current limit on "trailer" bytes following a RingbufResponseV0, which is
+/// the largest chunk an agent will send. Allocate this much space beyond the
+/// hubpack suggested size.
+pub const RINGBUF_RESP_V0_TRAILER: usize = 512;
+
+/// Strips the NUL padding from a fixed-size string field.
+fn unpad(field: &[u8]) -> Option<&str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).ok()
+}
+
+/// A message that may be followed by a trailer of raw bytes.
+///
+/// Buffer sizes are computed from `MAX_TRAILER` at compile time, so it must
+/// be at least as large as anything `max_trailer` can return; the framing
+/// functions reject trailers longer than `max_trailer`.
+pub trait HasTrailer {
+    /// Maximum number of trailer bytes that may follow any value of this type.
+    const MAX_TRAILER: usize;
+
+    /// Maximum number of trailer bytes that may follow this particular value.
+    /// Variants documented as having no data attached return 0.
+    fn max_trailer(&self) -> usize;
+}
+
+/// Common interface to the per-query response types, used by the framing
+/// functions.
+///
+/// The encoding of a response must never begin with `ERROR_RESPONSE_MARKER` or
+/// `RESPONSE_V1_MARKER`. For the enums in this file, that means they must stay
+/// below 254 variants.
+pub trait Response:
+    HasTrailer + Serialize + DeserializeOwned + SerializedSize
+{
+}
+
+/// Maximum size of an encoded `T`, including its trailer.
+const fn max_size<T: Response>() -> usize {
+    T::MAX_SIZE + T::MAX_TRAILER
+}
+
+/// Response sent in place of the per-query response when the agent can't
+/// process a request at all -- for instance, because it was sent by a client
+/// newer than the agent's firmware.
+///
+/// On the wire, this is the byte `ERROR_RESPONSE_MARKER` followed by the
+/// encoded variant, with no trailer. Since no per-query response can begin with
+/// that byte, clients can recognize an error response regardless of which
+/// query they sent; `decode_response` does this automatically. The variants in
+/// this enum _are_ the protocol definition. Add variants only at the end.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum ErrorResponse {
+    /// The request's version byte doesn't match any `Request` variant this
+    /// agent knows.
+    UnsupportedVersion,
+
+    /// The request's version is known, but the query within it isn't. This
+    /// usually means the query was added after the agent's firmware was built.
+    UnknownQuery,
+
+    /// The request couldn't be decoded for some other reason, such as being
+    /// truncated or carrying an oversized trailer.
+    MalformedRequest,
+}
+
+/// First byte of every `ErrorResponse` packet.
+pub const ERROR_RESPONSE_MARKER: u8 = 0xff;
+
+/// Size of an encoded `ErrorResponse` packet, including the marker.
+pub const ERROR_RESPONSE_SIZE: usize = 1 + ErrorResponse::MAX_SIZE;
+
+/// Header at the start of every response to a `Request::V1`.
+///
+/// On the wire, this is the byte `RESPONSE_V1_MARKER` followed by the encoded
+/// header, followed by exactly what would have been sent in response to the
+/// equivalent `Request::V0` -- either the per-query response and its trailer,
+/// or an `ErrorResponse`. If the agent can't decode the ID from a request, it
+/// sends a bare `ErrorResponse` with no header instead.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct ResponseHeaderV1 {
+    /// The `id` from the request being answered.
+    pub id: u32,
+}
+
+/// First byte of every response to a `Request::V1`.
+pub const RESPONSE_V1_MARKER: u8 = 0xfe;
+
+/// Size of the `ResponseHeaderV1`, including the marker.
+pub const RESPONSE_V1_HEADER_SIZE: usize = 1 + ResponseHeaderV1::MAX_SIZE;
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use std::fmt::Debug;
+
+    #[test]
+    fn v0_query_encoding_check() {
+        for (i, query) in QueryV0::ALL.into_iter().enumerate() {
+            let mut encoded = [0; QueryV0::MAX_SIZE];
+            let len = hubpack::serialize(&mut encoded, &query).unwrap();
+            assert_eq!(len, 1);
+            assert_eq!(encoded[0], i as u8);
+        }
+        // Anything past the end of `ALL` is not a query, so `ALL` is complete.
+        let next = [QueryV0::ALL.len() as u8];
+        assert!(hubpack::deserialize::<QueryV0>(&next).is_err());
+    }
 
     #[test]
-    fn v0_seq_regs_encoding_check() {
-        let message = Request::V0(QueryV0::SequencerRegisters);
+    fn max_request_version_check() {
+        let message = Request::V1 {
+            id: 0,
+            query: QueryV0::SequencerRegisters,
+        };
         let mut encoded = [0; Request::MAX_SIZE];
-        let len = hubpack::serialize(&mut encoded, &message).unwrap();
+        hubpack::serialize(&mut encoded, &message).unwrap();
+        assert_eq!(encoded[0], MAX_REQUEST_VERSION);
+    }
 
-        assert_eq!(len, 2);
-        assert_eq!(
-            &encoded[..2],
-            &[
-                0, // encoded version
-                0, // sequencer registers query
-            ]
-        );
+    #[test]
+    fn capabilities_bitmap_check() {
+        for query in QueryV0::ALL {
+            assert!(CapabilitiesV0::CURRENT.supports(query));
+        }
+        let old = CapabilitiesV0 {
+            max_request_version: 0,
+            queries: [1; 32],
+        };
+        assert!(old.supports(QueryV0::SequencerRegisters));
+        assert!(!old.supports(QueryV0::Capabilities));
     }
 
     #[test]
-    fn v0_seq_regs_response_encoding_check() {
-        // This test checks that these three variants serialize to dense small
-        // integers starting at 0, in this exact order:
-        let variants = [
-            SequencerRegistersResponseV0::Success,
-            SequencerRegistersResponseV0::SequencerTaskDead,
-            SequencerRegistersResponseV0::SequencerReadRegsFailed,
-        ];
-        for (i, v) in variants.into_iter().enumerate() {
-            let mut encoded = [0; SequencerRegistersResponseV0::MAX_SIZE];
-            let len = hubpack::serialize(&mut encoded, &v).unwrap();
-            assert_eq!(len, 1);
-            assert_eq!(encoded[0], i as u8);
+    fn identity_strings() {
+        let mut id = IdentityV0 {
+            model: [0; 16],
+            revision: 0,
+            serial: [0xff; 16],
+            image_version: 0,
+            git_commit: [0; 20],
+            fpga_bitstream: 0,
+        };
+        assert_eq!(id.model_str(), Some(""));
+        assert_eq!(id.serial_str(), None);
+        id.model[..11].copy_from_slice(b"913-0000019");
+        id.serial = *b"0123456789abcdef";
+        assert_eq!(id.model_str(), Some("913-0000019"));
+        assert_eq!(id.serial_str(), Some("0123456789abcdef"));
+        assert_eq!(IdentityV0::MAX_SIZE, 64);
+    }
+
+    /// Checks that `value` survives a trip through `hubpack`, encoding to no
+    /// more than `T::MAX_SIZE` bytes.
+    fn check_round_trip<T>(value: T) -> Result<(), TestCaseError>
+    where
+        T: Serialize + DeserializeOwned + SerializedSize + PartialEq + Debug,
+    {
+        let mut encoded = vec![0; T::MAX_SIZE + 8];
+        let len = hubpack::serialize(&mut encoded, &value).unwrap();
+        prop_assert!(len <= T::MAX_SIZE);
+        let (decoded, rest) = hubpack::deserialize::<T>(&encoded[..len])?;
+        prop_assert_eq!(decoded, value);
+        prop_assert!(rest.is_empty());
+        Ok(())
+    }
+
+    /// Checks that `value` never allows more trailer than its type promises,
+    /// since buffers are sized by the latter.
+    fn check_max_trailer<T: HasTrailer>(
+        value: &T,
+    ) -> Result<(), TestCaseError> {
+        prop_assert!(value.max_trailer() <= T::MAX_TRAILER);
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn request_round_trip(v: Request) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn query_v0_round_trip(v: QueryV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn seq_regs_response_round_trip(v: SequencerRegistersResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn capabilities_response_round_trip(v: CapabilitiesResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn temperatures_response_round_trip(v: TemperaturesResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn temperature_record_round_trip(v: sensors::TemperatureRecord) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn power_rails_response_round_trip(v: PowerRailsResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn power_rail_record_round_trip(v: sensors::PowerRailRecord) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn host_power_state_response_round_trip(v: HostPowerStateResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn power_transition_round_trip(v: power::PowerTransition) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn tasks_response_round_trip(v: TasksResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn task_record_round_trip(v: tasks::TaskRecord) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn identity_response_round_trip(v: IdentityResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn fpga_status_response_round_trip(v: FpgaStatusResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn post_codes_response_round_trip(v: PostCodesResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn ringbuf_request_round_trip(v: RingbufRequestV0) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn ringbuf_response_round_trip(v: RingbufResponseV0) {
+            check_max_trailer(&v)?;
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn error_response_round_trip(v: ErrorResponse) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn response_header_v1_round_trip(v: ResponseHeaderV1) {
+            check_round_trip(v)?;
+        }
+
+        #[test]
+        fn seq_regs_frame_round_trip(
+            id: u32,
+            response: SequencerRegistersResponseV0,
+            trailer in proptest::collection::vec(
+                any::<u8>(),
+                0..=SEQ_REG_RESP_V0_TRAILER,
+            ),
+        ) {
+            let trailer = &trailer[..trailer.len().min(response.max_trailer())];
+            let mut encoded = [0; ANY_RESPONSE_V1_MAX_SIZE];
+            let len =
+                encode_response_v1(id, &response, trailer, &mut encoded)
+                    .unwrap();
+            prop_assert_eq!(
+                decode_response_v1(id, &encoded[..len]),
+                Ok((response, trailer)),
+            );
         }
     }
 }
@@ -0,0 +1,624 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Scriptable inspector agent for testing clients without hardware. Requires
+//! the `std` feature.
+//!
+//! The mock listens on a loopback UDP port and answers requests through
+//! `server::dispatch`, so it speaks exactly the wire format a real agent does.
+//! Each query has a queue of scripted replies, consumed one per request; once
+//! the queue is empty, the query's default reply is used.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::power::HostPowerState;
+use crate::server::{dispatch, InspectorHandler};
+use crate::{
+    decode_request, CapabilitiesResponseV0, CapabilitiesV0, FpgaConfigState,
+    FpgaStatusResponseV0, FpgaStatusV0, HasTrailer, HostPowerStateResponseV0,
+    IdentityResponseV0, IdentityV0, PostCodesResponseV0, PowerRailsResponseV0,
+    QueryV0, Request, RingbufRequestV0, RingbufResponseV0,
+    SequencerRegistersResponseV0, TasksResponseV0, TemperaturesResponseV0,
+    ANY_RESPONSE_V1_MAX_SIZE, HOST_POWER_RESP_V0_TRAILER,
+    POST_CODES_RESP_V0_TRAILER, POWER_RAILS_RESP_V0_TRAILER, REQUEST_MAX_SIZE,
+    RINGBUF_RESP_V0_TRAILER, SEQ_REG_RESP_V0_TRAILER, TASKS_RESP_V0_TRAILER,
+    TEMPS_RESP_V0_TRAILER,
+};
+
+/// How often the agent thread checks whether it should exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A scripted reply to one request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MockReply {
+    /// Answers `QueryV0::SequencerRegisters` with the given response and
+    /// trailer.
+    SequencerRegisters(SequencerRegistersResponseV0, Vec<u8>),
+    /// Answers `QueryV0::Capabilities` with the given response.
+    Capabilities(CapabilitiesResponseV0),
+    /// Answers `QueryV0::Temperatures` with the given response and trailer.
+    Temperatures(TemperaturesResponseV0, Vec<u8>),
+    /// Answers `QueryV0::PowerRails` with the given response and trailer.
+    PowerRails(PowerRailsResponseV0, Vec<u8>),
+    /// Answers `QueryV0::HostPowerState` with the given response and trailer.
+    HostPowerState(HostPowerStateResponseV0, Vec<u8>),
+    /// Answers `QueryV0::Tasks` with the given response and trailer.
+    Tasks(TasksResponseV0, Vec<u8>),
+    /// Answers `QueryV0::Identity` with the given response.
+    Identity(IdentityResponseV0),
+    /// Answers `QueryV0::FpgaStatus` with the given response.
+    FpgaStatus(FpgaStatusResponseV0),
+    /// Answers `QueryV0::PostCodes` with the given response and trailer.
+    PostCodes(PostCodesResponseV0, Vec<u8>),
+    /// Answers `QueryV0::Ringbuf` with the given response and trailer,
+    /// whatever chunk was asked for.
+    Ringbuf(RingbufResponseV0, Vec<u8>),
+    /// Waits before sending the inner reply. The agent handles one request at
+    /// a time, so this delays any requests queued up behind it too.
+    Delay(Duration, Box<MockReply>),
+    /// Sends nothing at all, as if the request or reply were lost.
+    Drop,
+}
+
+impl MockReply {
+    /// Returns the reply used for `query` when nothing has been scripted: a
+    /// successful response, with an all-zero register dump or an
+    /// empty list of readings where applicable. The host is reported as being
+    /// in A2 with no history, the image as having no tasks, and the SP as an
+    /// unprogrammed board running image version 0 with a configured FPGA. POST
+    /// codes are answered with `NoHostPower`, to match the power state, and
+    /// there are no ringbufs.
+    pub fn default_for(query: QueryV0) -> Self {
+        match query {
+            QueryV0::SequencerRegisters => Self::SequencerRegisters(
+                SequencerRegistersResponseV0::Success,
+                vec![0; SEQ_REG_RESP_V0_TRAILER],
+            ),
+            QueryV0::Capabilities => Self::Capabilities(
+                CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT),
+            ),
+            QueryV0::Temperatures => {
+                Self::Temperatures(TemperaturesResponseV0::Success, vec![])
+            }
+            QueryV0::PowerRails => {
+                Self::PowerRails(PowerRailsResponseV0::Success, vec![])
+            }
+            QueryV0::HostPowerState => Self::HostPowerState(
+                HostPowerStateResponseV0::Success {
+                    state: HostPowerState::A2,
+                    now: 0,
+                },
+                vec![],
+            ),
+            QueryV0::Tasks => {
+                Self::Tasks(TasksResponseV0::Success { total: 0 }, vec![])
+            }
+            QueryV0::Identity => {
+                Self::Identity(IdentityResponseV0::Success(IdentityV0 {
+                    model: [0; 16],
+                    revision: 0,
+                    serial: [0; 16],
+                    image_version: 0,
+                    git_commit: [0; 20],
+                    fpga_bitstream: 0,
+                }))
+            }
+            QueryV0::FpgaStatus => {
+                Self::FpgaStatus(FpgaStatusResponseV0::Success(FpgaStatusV0 {
+                    state: FpgaConfigState::Configured,
+                    checksum: 0,
+                    expected_checksum: 0,
+                    error_code: 0,
+                }))
+            }
+            QueryV0::PostCodes => {
+                Self::PostCodes(PostCodesResponseV0::NoHostPower, vec![])
+            }
+            QueryV0::Ringbuf => {
+                Self::Ringbuf(RingbufResponseV0::NoSuchRingbuf, vec![])
+            }
+        }
+    }
+
+    /// Checks whether this reply can be sent in answer to `query`.
+    fn answers(&self, query: QueryV0) -> bool {
+        match self {
+            Self::SequencerRegisters(..) => {
+                query == QueryV0::SequencerRegisters
+            }
+            Self::Capabilities(_) => query == QueryV0::Capabilities,
+            Self::Temperatures(..) => query == QueryV0::Temperatures,
+            Self::PowerRails(..) => query == QueryV0::PowerRails,
+            Self::HostPowerState(..) => query == QueryV0::HostPowerState,
+            Self::Tasks(..) => query == QueryV0::Tasks,
+            Self::Identity(_) => query == QueryV0::Identity,
+            Self::FpgaStatus(_) => query == QueryV0::FpgaStatus,
+            Self::PostCodes(..) => query == QueryV0::PostCodes,
+            Self::Ringbuf(..) => query == QueryV0::Ringbuf,
+            Self::Delay(_, reply) => reply.answers(query),
+            Self::Drop => true,
+        }
+    }
+
+    /// Checks whether this reply's trailer is no longer than its response
+    /// allows, so that it can be encoded.
+    fn trailer_fits(&self) -> bool {
+        let (max, len) = match self {
+            Self::SequencerRegisters(r, t) => (r.max_trailer(), t.len()),
+            Self::Temperatures(r, t) => (r.max_trailer(), t.len()),
+            Self::PowerRails(r, t) => (r.max_trailer(), t.len()),
+            Self::HostPowerState(r, t) => (r.max_trailer(), t.len()),
+            Self::Tasks(r, t) => (r.max_trailer(), t.len()),
+            Self::PostCodes(r, t) => (r.max_trailer(), t.len()),
+            Self::Ringbuf(r, t) => (r.max_trailer(), t.len()),
+            Self::Delay(_, reply) => return reply.trailer_fits(),
+            Self::Capabilities(_)
+            | Self::Identity(_)
+            | Self::FpgaStatus(_)
+            | Self::Drop => return true,
+        };
+        len <= max
+    }
+
+    /// Panics unless this reply can be sent in answer to `query`.
+    fn check(&self, query: QueryV0) {
+        assert!(self.answers(query), "{self:?} can't answer {query:?}");
+        assert!(
+            self.trailer_fits(),
+            "{self:?} has a longer trailer than its response allows",
+        );
+    }
+}
+
+/// A mock agent running on its own thread. The thread is stopped when this is
+/// dropped.
+#[derive(Debug)]
+pub struct MockAgent {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Scripted and default replies, indexed by query.
+    scripts: Vec<(VecDeque<MockReply>, MockReply)>,
+    /// Every request that decoded successfully, in order of arrival.
+    received: Vec<Request>,
+}
+
+impl MockAgent {
+    /// Starts a mock agent on an OS-chosen loopback port.
+    pub fn start() -> io::Result<Self> {
+        Self::start_with(false)
+    }
+
+    /// Like `start`, but the agent behaves like firmware that predates
+    /// `Request::V1`: V1 requests are recorded by `received`, but never
+    /// answered.
+    pub fn start_v0_only() -> io::Result<Self> {
+        Self::start_with(true)
+    }
+
+    fn start_with(v0_only: bool) -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = socket.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            scripts: QueryV0::ALL
+                .into_iter()
+                .map(|q| (VecDeque::new(), MockReply::default_for(q)))
+                .collect(),
+            received: Vec::new(),
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let state = Arc::clone(&state);
+            let shutdown = Arc::clone(&shutdown);
+            move || serve(socket, &state, &shutdown, v0_only)
+        });
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address clients should send requests to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queues `reply` to be sent in answer to the next request for `query`
+    /// that doesn't already have a reply queued.
+    ///
+    /// # Panics
+    ///
+    /// If `reply` is a response to some other query, or its trailer is longer
+    /// than the response allows.
+    pub fn push(&self, query: QueryV0, reply: MockReply) {
+        reply.check(query);
+        self.state.lock().unwrap().scripts[query as usize]
+            .0
+            .push_back(reply);
+    }
+
+    /// Sets the reply sent for `query` once its queue is empty.
+    ///
+    /// # Panics
+    ///
+    /// If `reply` is a response to some other query, or its trailer is longer
+    /// than the response allows.
+    pub fn set_default(&self, query: QueryV0, reply: MockReply) {
+        reply.check(query);
+        self.state.lock().unwrap().scripts[query as usize].1 = reply;
+    }
+
+    /// Returns every request received so far that could be decoded,
+    /// including ones whose reply was dropped.
+    pub fn received(&self) -> Vec<Request> {
+        self.state.lock().unwrap().received.clone()
+    }
+}
+
+impl Drop for MockAgent {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            // A panic on the agent thread has already been reported; don't
+            // double-panic here.
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(
+    socket: UdpSocket,
+    state: &Mutex<State>,
+    shutdown: &AtomicBool,
+    v0_only: bool,
+) {
+    let mut packet_in = [0; REQUEST_MAX_SIZE];
+    let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+    while !shutdown.load(Ordering::Relaxed) {
+        let (n, from) = match socket.recv_from(&mut packet_in) {
+            Ok(r) => r,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => panic!("mock agent socket failed: {e}"),
+        };
+
+        // Undecodable requests go straight to `dispatch`, which answers them
+        // with an `ErrorResponse`; there's nothing to script for them.
+        let mut reply = None;
+        if let Ok((request, _)) = decode_request(&packet_in[..n]) {
+            let mut state = state.lock().unwrap();
+            state.received.push(request);
+            if v0_only && matches!(request, Request::V1 { .. }) {
+                continue;
+            }
+            let (queue, default) = &mut state.scripts[request.query() as usize];
+            reply = Some(queue.pop_front().unwrap_or_else(|| default.clone()));
+        }
+        while let Some(MockReply::Delay(delay, inner)) = reply {
+            thread::sleep(delay);
+            reply = Some(*inner);
+        }
+        if reply == Some(MockReply::Drop) {
+            continue;
+        }
+
+        let mut handler = Scripted(reply);
+        match dispatch(&packet_in[..n], &mut packet_out, &mut handler) {
+            Ok(len) => {
+                // Sends only fail if the client has gone away, which is
+                // equivalent to the reply being lost.
+                let _ = socket.send_to(&packet_out[..len], from);
+            }
+            Err(e) => panic!("mock agent can't encode {:?}: {e:?}", handler.0),
+        }
+    }
+}
+
+/// Handler that answers with a reply chosen before dispatch.
+struct Scripted(Option<MockReply>);
+
+impl InspectorHandler for Scripted {
+    fn sequencer_registers(
+        &mut self,
+        trailer: &mut [u8; SEQ_REG_RESP_V0_TRAILER],
+    ) -> (SequencerRegistersResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::SequencerRegisters(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for sequencer_registers"),
+        }
+    }
+
+    fn capabilities(&mut self) -> CapabilitiesResponseV0 {
+        match &self.0 {
+            Some(MockReply::Capabilities(response)) => *response,
+            other => unreachable!("{other:?} scripted for capabilities"),
+        }
+    }
+
+    fn temperatures(
+        &mut self,
+        trailer: &mut [u8; TEMPS_RESP_V0_TRAILER],
+    ) -> (TemperaturesResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::Temperatures(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for temperatures"),
+        }
+    }
+
+    fn power_rails(
+        &mut self,
+        trailer: &mut [u8; POWER_RAILS_RESP_V0_TRAILER],
+    ) -> (PowerRailsResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::PowerRails(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for power_rails"),
+        }
+    }
+
+    fn host_power_state(
+        &mut self,
+        trailer: &mut [u8; HOST_POWER_RESP_V0_TRAILER],
+    ) -> (HostPowerStateResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::HostPowerState(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for host_power_state"),
+        }
+    }
+
+    fn tasks(
+        &mut self,
+        trailer: &mut [u8; TASKS_RESP_V0_TRAILER],
+    ) -> (TasksResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::Tasks(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for tasks"),
+        }
+    }
+
+    fn identity(&mut self) -> IdentityResponseV0 {
+        match &self.0 {
+            Some(MockReply::Identity(response)) => *response,
+            other => unreachable!("{other:?} scripted for identity"),
+        }
+    }
+
+    fn fpga_status(&mut self) -> FpgaStatusResponseV0 {
+        match &self.0 {
+            Some(MockReply::FpgaStatus(response)) => *response,
+            other => unreachable!("{other:?} scripted for fpga_status"),
+        }
+    }
+
+    fn post_codes(
+        &mut self,
+        trailer: &mut [u8; POST_CODES_RESP_V0_TRAILER],
+    ) -> (PostCodesResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::PostCodes(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for post_codes"),
+        }
+    }
+
+    fn ringbuf(
+        &mut self,
+        _request: RingbufRequestV0,
+        trailer: &mut [u8; RINGBUF_RESP_V0_TRAILER],
+    ) -> (RingbufResponseV0, usize) {
+        match &self.0 {
+            Some(MockReply::Ringbuf(response, bytes)) => {
+                (*response, copy_trailer(bytes, trailer))
+            }
+            other => unreachable!("{other:?} scripted for ringbuf"),
+        }
+    }
+}
+
+/// Copies a scripted trailer into `trailer`. `MockAgent::push` and
+/// `set_default` have already checked that it fits, but the scripted length is
+/// returned regardless, so that `dispatch` would reject an oversized trailer
+/// rather than silently truncating it.
+fn copy_trailer(bytes: &[u8], trailer: &mut [u8]) -> usize {
+    let len = bytes.len().min(trailer.len());
+    trailer[..len].copy_from_slice(&bytes[..len]);
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClientError, InspectorClient, RequestVersion};
+    use crate::ringbuf::RingbufError;
+    use crate::ErrorResponse;
+
+    fn client() -> InspectorClient {
+        InspectorClient::bind("127.0.0.1:0")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100))
+            .with_retries(0)
+            .with_version(RequestVersion::V1)
+    }
+
+    #[test]
+    fn scripted_replies() {
+        let agent = MockAgent::start().unwrap();
+        let client = client();
+        agent.push(
+            QueryV0::SequencerRegisters,
+            MockReply::SequencerRegisters(
+                SequencerRegistersResponseV0::Success,
+                vec![0xaa; SEQ_REG_RESP_V0_TRAILER],
+            ),
+        );
+        agent.push(
+            QueryV0::SequencerRegisters,
+            MockReply::SequencerRegisters(
+                SequencerRegistersResponseV0::SequencerTaskDead,
+                vec![],
+            ),
+        );
+
+        let (response, trailer) =
+            client.sequencer_registers(agent.addr()).unwrap();
+        assert_eq!(response, SequencerRegistersResponseV0::Success);
+        assert_eq!(trailer, [0xaa; SEQ_REG_RESP_V0_TRAILER]);
+
+        let (response, trailer) =
+            client.sequencer_registers(agent.addr()).unwrap();
+        assert_eq!(response, SequencerRegistersResponseV0::SequencerTaskDead);
+        assert!(trailer.is_empty());
+
+        // Queue exhausted, so we get the default.
+        let default = MockReply::default_for(QueryV0::SequencerRegisters);
+        let (response, trailer) =
+            client.sequencer_registers(agent.addr()).unwrap();
+        assert_eq!(default, MockReply::SequencerRegisters(response, trailer));
+        assert_eq!(agent.received().len(), 3);
+    }
+
+    #[test]
+    fn dropped_replies() {
+        let agent = MockAgent::start().unwrap();
+        agent.push(QueryV0::Capabilities, MockReply::Drop);
+        match client().capabilities(agent.addr()) {
+            Err(ClientError::Timeout { attempts: 1 }) => (),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+
+        // With a retry, the client survives a single drop.
+        agent.push(QueryV0::Capabilities, MockReply::Drop);
+        let response =
+            client().with_retries(1).capabilities(agent.addr()).unwrap();
+        assert_eq!(
+            response,
+            CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT)
+        );
+        assert_eq!(agent.received().len(), 3);
+    }
+
+    #[test]
+    fn delayed_replies() {
+        let agent = MockAgent::start().unwrap();
+        agent.push(
+            QueryV0::Capabilities,
+            MockReply::Delay(
+                Duration::from_millis(300),
+                Box::new(MockReply::default_for(QueryV0::Capabilities)),
+            ),
+        );
+        match client().capabilities(agent.addr()) {
+            Err(ClientError::Timeout { attempts: 1 }) => (),
+            other => panic!("expected timeout, got {other:?}"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "longer trailer")]
+    fn oversized_trailer_rejected() {
+        let agent = MockAgent::start().unwrap();
+        agent.push(
+            QueryV0::SequencerRegisters,
+            MockReply::SequencerRegisters(
+                SequencerRegistersResponseV0::Success,
+                vec![0; SEQ_REG_RESP_V0_TRAILER + 1],
+            ),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "longer trailer")]
+    fn trailer_on_failure_rejected() {
+        let agent = MockAgent::start().unwrap();
+        agent.set_default(
+            QueryV0::PostCodes,
+            MockReply::Delay(
+                Duration::from_millis(1),
+                Box::new(MockReply::PostCodes(
+                    PostCodesResponseV0::NoHostPower,
+                    vec![0],
+                )),
+            ),
+        );
+    }
+
+    #[test]
+    fn bad_requests_get_error_responses() {
+        let agent = MockAgent::start().unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        socket.send_to(&[0xee], agent.addr()).unwrap();
+        let mut buf = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(
+            crate::decode_response::<SequencerRegistersResponseV0>(&buf[..n]),
+            Err(crate::FrameError::ErrorResponse(
+                ErrorResponse::UnsupportedVersion
+            )),
+        );
+        assert!(agent.received().is_empty());
+    }
+
+    #[test]
+    fn ringbuf_chunks_reassembled() {
+        let agent = MockAgent::start().unwrap();
+        let client = client();
+        for (chunk, next) in [(vec![1, 2, 3], Some(3)), (vec![4, 5], None)] {
+            agent.push(
+                QueryV0::Ringbuf,
+                MockReply::Ringbuf(
+                    RingbufResponseV0::Success { total: 5, next },
+                    chunk,
+                ),
+            );
+        }
+        assert_eq!(
+            client.read_ringbuf(agent.addr(), 0).unwrap(),
+            [1, 2, 3, 4, 5]
+        );
+
+        // With nothing scripted, there are no ringbufs.
+        match client.read_ringbuf(agent.addr(), 0) {
+            Err(ClientError::Ringbuf(RingbufError::Response(
+                RingbufResponseV0::NoSuchRingbuf,
+            ))) => (),
+            other => panic!("expected NoSuchRingbuf, got {other:?}"),
+        }
+        assert_eq!(agent.received().len(), 3);
+    }
+}
@@ -0,0 +1,82 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Host power state and its transition history, as reported in response to
+//! `QueryV0::HostPowerState`.
+//!
+//! As with the top-level protocol enums, the order and presence of the
+//! variants of the enums here _is_ the protocol definition: add variants only
+//! at the end.
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+/// Host power states, as tracked by the SP's sequencer task.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum HostPowerState {
+    /// Only the SP's always-on domain is powered.
+    A2,
+    /// As `A2`, with the fans powered.
+    A2PlusFans,
+    /// The A1 domain is powered, but the host CPU is not running.
+    A1,
+    /// The host is powered and running.
+    A0,
+    /// As `A0`, with hot-pluggable devices powered.
+    A0PlusHP,
+    /// The host CPU asserted THERMTRIP and the sequencer cut power to it.
+    A0Thermtrip,
+    /// The host is being reset.
+    A0Reset,
+}
+
+/// Reasons the host can change power state.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TransitionCause {
+    /// The SP booted and entered its initial state.
+    SpBoot,
+    /// The control plane asked for the new state.
+    ControlPlane,
+    /// The host asked to power off or reboot.
+    HostRequest,
+    /// The host CPU asserted THERMTRIP.
+    Thermtrip,
+    /// The sequencer reported a fault, such as a rail failing to come up; its
+    /// register dump has the details.
+    SequencerFault,
+    /// Anything not covered by the other variants.
+    Other,
+}
+
+/// One entry in the host power state history, as found in the trailer of
+/// `HostPowerStateResponseV0::Success`.
+///
+/// Each record occupies `PowerTransition::MAX_SIZE` (11) bytes:
+///
+/// | Offset | Size | Field                                    |
+/// |--------|------|------------------------------------------|
+/// | 0      | 8    | `timestamp`, little-endian `u64`         |
+/// | 8      | 1    | `from`, as a `HostPowerState` index      |
+/// | 9      | 1    | `to`, as a `HostPowerState` index        |
+/// | 10     | 1    | `cause`, as a `TransitionCause` index    |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct PowerTransition {
+    /// When the transition happened, in milliseconds since the SP booted.
+    pub timestamp: u64,
+    /// State before the transition.
+    pub from: HostPowerState,
+    /// State after the transition.
+    pub to: HostPowerState,
+    /// Why the transition happened.
+    pub cause: TransitionCause,
+}
@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Queries named at the type level.
+//!
+//! Each `QueryV0` variant has a marker type here of the same name, which
+//! implements `Query` to say what the agent sends back. Code that is generic
+//! over `Query` then gets the right response type checked at compile time,
+//! rather than by convention: `client.call::<query::SequencerRegisters>(sp)`
+//! returns a `SequencerRegistersResponseV0`.
+
+use crate::{
+    CapabilitiesResponseV0, FpgaStatusResponseV0, HostPowerStateResponseV0,
+    IdentityResponseV0, PostCodesResponseV0, PowerRailsResponseV0, QueryV0,
+    Response, RingbufResponseV0, SequencerRegistersResponseV0, TasksResponseV0,
+    TemperaturesResponseV0,
+};
+
+/// A query, along with the type of its response.
+pub trait Query {
+    /// The query to put on the wire.
+    const QUERY: QueryV0;
+
+    /// Maximum number of trailer bytes that may follow a request for this
+    /// query.
+    const MAX_TRAILER: usize = Self::QUERY.request_trailer();
+
+    /// What the agent sends back, unless it sends an `ErrorResponse`.
+    type Response: Response;
+}
+
+/// Defines a marker type for each query, and checks that every `QueryV0`
+/// variant has one.
+macro_rules! queries {
+    ($($name:ident => $response:ty,)*) => {
+        $(
+            #[doc = concat!("Marker for `QueryV0::", stringify!($name), "`.")]
+            #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+            pub struct $name;
+
+            impl Query for $name {
+                const QUERY: QueryV0 = QueryV0::$name;
+                type Response = $response;
+            }
+        )*
+
+        #[allow(dead_code)]
+        fn exhaustive(query: QueryV0) {
+            match query {
+                $(QueryV0::$name => (),)*
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+            use crate::HasTrailer;
+            use hubpack::SerializedSize;
+
+            /// Checks that `Q` agrees with the `QueryV0` sizes that buffers
+            /// are built from.
+            fn check<Q: Query>() {
+                assert_eq!(
+                    Q::QUERY.max_response_size(),
+                    Q::Response::MAX_SIZE + Q::Response::MAX_TRAILER,
+                    "{:?}",
+                    Q::QUERY,
+                );
+                assert_eq!(Q::MAX_TRAILER, Q::QUERY.max_trailer());
+            }
+
+            #[test]
+            fn sizes_match_query_v0() {
+                $(check::<$name>();)*
+            }
+        }
+    };
+}
+
+queries! {
+    SequencerRegisters => SequencerRegistersResponseV0,
+    Capabilities => CapabilitiesResponseV0,
+    Temperatures => TemperaturesResponseV0,
+    PowerRails => PowerRailsResponseV0,
+    HostPowerState => HostPowerStateResponseV0,
+    Tasks => TasksResponseV0,
+    Identity => IdentityResponseV0,
+    FpgaStatus => FpgaStatusResponseV0,
+    PostCodes => PostCodesResponseV0,
+    Ringbuf => RingbufResponseV0,
+}
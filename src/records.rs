@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Trailers made up of fixed-size records.
+//!
+//! Several responses carry a variable number of records in their trailer. Each
+//! record is a `hubpack`-encoded value padded to `T::MAX_SIZE` bytes, so the
+//! number of records is the trailer length divided by the record size.
+//!
+//! Some trailers instead start with the number of records, as a little-endian
+//! `u16`; see `encode_prefixed_records`.
+
+use core::marker::PhantomData;
+
+use hubpack::SerializedSize;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Encodes `records` back to back into `out`, returning the number of bytes
+/// used.
+pub fn encode_records<T: Serialize + SerializedSize>(
+    records: &[T],
+    out: &mut [u8],
+) -> Result<usize, hubpack::Error> {
+    let end = records.len() * T::MAX_SIZE;
+    let out = out.get_mut(..end).ok_or(hubpack::Error::Overrun)?;
+    for (record, chunk) in records.iter().zip(out.chunks_exact_mut(T::MAX_SIZE))
+    {
+        let len = hubpack::serialize(chunk, record)?;
+        chunk[len..].fill(0);
+    }
+    Ok(end)
+}
+
+/// Size of the record count at the start of a length-prefixed trailer.
+pub const PREFIX_LEN: usize = 2;
+
+/// Encodes the number of records, followed by `records` back to back, into
+/// `out`, returning the number of bytes used.
+pub fn encode_prefixed_records<T: Serialize + SerializedSize>(
+    records: &[T],
+    out: &mut [u8],
+) -> Result<usize, hubpack::Error> {
+    let count =
+        u16::try_from(records.len()).map_err(|_| hubpack::Error::Custom)?;
+    let (prefix, rest) = out
+        .split_first_chunk_mut::<PREFIX_LEN>()
+        .ok_or(hubpack::Error::Overrun)?;
+    let len = encode_records(records, rest)?;
+    *prefix = count.to_le_bytes();
+    Ok(PREFIX_LEN + len)
+}
+
+/// Returns an iterator over the records in a length-prefixed `trailer`.
+///
+/// Fails with `hubpack::Error::Truncated` if the trailer is shorter than its
+/// count says. Bytes after the last record are ignored.
+pub fn decode_prefixed_records<T: DeserializeOwned + SerializedSize>(
+    trailer: &[u8],
+) -> Result<Records<'_, T>, hubpack::Error> {
+    let (prefix, rest) = trailer
+        .split_first_chunk::<PREFIX_LEN>()
+        .ok_or(hubpack::Error::Truncated)?;
+    let len = usize::from(u16::from_le_bytes(*prefix)) * T::MAX_SIZE;
+    let rest = rest.get(..len).ok_or(hubpack::Error::Truncated)?;
+    Ok(decode_records(rest))
+}
+
+/// Returns an iterator over the records in `trailer`.
+///
+/// Each record decodes independently, so a record the client can't decode
+/// (because it uses an enum variant added after the client was built, say)
+/// doesn't prevent decoding the rest. A partial record at the end of the
+/// trailer is reported as `hubpack::Error::Truncated`.
+pub fn decode_records<T: DeserializeOwned + SerializedSize>(
+    trailer: &[u8],
+) -> Records<'_, T> {
+    Records {
+        remaining: trailer,
+        _record: PhantomData,
+    }
+}
+
+/// Iterator returned by `decode_records`.
+#[derive(Clone, Debug)]
+pub struct Records<'a, T> {
+    remaining: &'a [u8],
+    _record: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned + SerializedSize> Iterator for Records<'_, T> {
+    type Item = Result<T, hubpack::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        if self.remaining.len() < T::MAX_SIZE {
+            self.remaining = &[];
+            return Some(Err(hubpack::Error::Truncated));
+        }
+        let (chunk, rest) = self.remaining.split_at(T::MAX_SIZE);
+        self.remaining = rest;
+        Some(hubpack::deserialize(chunk).map(|(record, _padding)| record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let records = [(1u16, 2u8), (3, 4), (5, 6)];
+        let mut buf = [0xff; 10];
+        let len = encode_records(&records, &mut buf).unwrap();
+        assert_eq!(len, 9);
+        assert_eq!(&buf[..len], &[1, 0, 2, 3, 0, 4, 5, 0, 6]);
+
+        let decoded: Vec<_> = decode_records::<(u16, u8)>(&buf[..len])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, records);
+
+        assert_eq!(
+            encode_records(&records, &mut buf[..8]),
+            Err(hubpack::Error::Overrun),
+        );
+    }
+
+    #[test]
+    fn prefixed_records_round_trip() {
+        let records = [0x0102_u16, 0x0304];
+        let mut buf = [0xff; 8];
+        let len = encode_prefixed_records(&records, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[2, 0, 2, 1, 4, 3]);
+
+        let decoded: Vec<_> = decode_prefixed_records::<u16>(&buf)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, records);
+
+        assert_eq!(
+            encode_prefixed_records(&records, &mut buf[..5]),
+            Err(hubpack::Error::Overrun),
+        );
+        assert!(matches!(
+            decode_prefixed_records::<u16>(&buf[..5]),
+            Err(hubpack::Error::Truncated),
+        ));
+        assert!(matches!(
+            decode_prefixed_records::<u16>(&[0]),
+            Err(hubpack::Error::Truncated),
+        ));
+    }
+
+    #[test]
+    fn records_truncated() {
+        let mut decoded = decode_records::<(u16, u8)>(&[1, 0, 2, 3]);
+        assert_eq!(decoded.next(), Some(Ok((1, 2))));
+        assert_eq!(decoded.next(), Some(Err(hubpack::Error::Truncated)));
+        assert_eq!(decoded.next(), None);
+    }
+}
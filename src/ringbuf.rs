@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client-side reassembly of ringbuf dumps, which arrive one chunk per
+//! `QueryV0::Ringbuf` response. Requires the `std` feature.
+//!
+//! `RingbufReader` doesn't do any I/O itself, so it works with any transport:
+//! send a request carrying `next_request()` as its trailer, `push` the
+//! response, and repeat until `next_request()` returns `None`.
+
+use crate::{RingbufRequestV0, RingbufResponseV0};
+
+/// Reassembles one ringbuf from a series of chunks.
+#[derive(Clone, Debug)]
+pub struct RingbufReader {
+    ringbuf: u16,
+    data: Vec<u8>,
+    total: Option<u32>,
+    done: bool,
+}
+
+impl RingbufReader {
+    /// Starts reading the ringbuf with the given index, from the beginning.
+    pub fn new(ringbuf: u16) -> Self {
+        Self {
+            ringbuf,
+            data: Vec::new(),
+            total: None,
+            done: false,
+        }
+    }
+
+    /// Returns the trailer for the next request to send, or `None` once the
+    /// whole ringbuf has been read.
+    pub fn next_request(&self) -> Option<RingbufRequestV0> {
+        if self.done {
+            return None;
+        }
+        Some(RingbufRequestV0 {
+            ringbuf: self.ringbuf,
+            offset: self.data.len() as u32,
+        })
+    }
+
+    /// Adds the chunk carried by the response to the request last returned by
+    /// `next_request`.
+    pub fn push(
+        &mut self,
+        response: RingbufResponseV0,
+        chunk: &[u8],
+    ) -> Result<(), RingbufError> {
+        let RingbufResponseV0::Success { total, next } = response else {
+            return Err(RingbufError::Response(response));
+        };
+        let offset = self.data.len() as u32;
+        let end = u64::from(offset) + chunk.len() as u64;
+        let total_end = u64::from(total);
+        if end > total_end || (next.is_none() && end != total_end) {
+            return Err(RingbufError::BadTotal {
+                offset,
+                len: chunk.len(),
+                total,
+            });
+        }
+        match next {
+            None => self.done = true,
+            Some(next) if u64::from(next) == end && !chunk.is_empty() => (),
+            Some(next) => {
+                return Err(RingbufError::BadCursor {
+                    offset,
+                    len: chunk.len(),
+                    next,
+                })
+            }
+        }
+        self.data.extend_from_slice(chunk);
+        self.total = Some(total);
+        Ok(())
+    }
+
+    /// Returns the size of the ringbuf as last reported by the agent, if any
+    /// chunks have arrived.
+    pub fn total(&self) -> Option<u32> {
+        self.total
+    }
+
+    /// Returns the data read so far, which is the whole ringbuf once
+    /// `next_request` returns `None`.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reasons reassembling a ringbuf can fail.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RingbufError {
+    /// The agent couldn't provide a chunk, and said why.
+    Response(RingbufResponseV0),
+    /// The agent's cursor doesn't follow on from the chunk it sent, so
+    /// continuing could skip data or loop forever.
+    BadCursor { offset: u32, len: usize, next: u32 },
+    /// The chunk runs past the `total` size the agent reported alongside it,
+    /// or is said to be the last one but ends short of `total`.
+    BadTotal { offset: u32, len: usize, total: u32 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembly() {
+        let mut reader = RingbufReader::new(3);
+        assert_eq!(
+            reader.next_request(),
+            Some(RingbufRequestV0 {
+                ringbuf: 3,
+                offset: 0
+            }),
+        );
+        let success = |next| RingbufResponseV0::Success { total: 5, next };
+        reader.push(success(Some(3)), &[1, 2, 3]).unwrap();
+        assert_eq!(
+            reader.next_request(),
+            Some(RingbufRequestV0 {
+                ringbuf: 3,
+                offset: 3
+            }),
+        );
+        reader.push(success(None), &[4, 5]).unwrap();
+        assert_eq!(reader.next_request(), None);
+        assert_eq!(reader.total(), Some(5));
+        assert_eq!(reader.into_data(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reassembly_errors() {
+        let mut reader = RingbufReader::new(0);
+        assert_eq!(
+            reader.push(RingbufResponseV0::NoSuchRingbuf, &[]),
+            Err(RingbufError::Response(RingbufResponseV0::NoSuchRingbuf)),
+        );
+        let stalled = RingbufResponseV0::Success {
+            total: 5,
+            next: Some(0),
+        };
+        assert_eq!(
+            reader.push(stalled, &[]),
+            Err(RingbufError::BadCursor {
+                offset: 0,
+                len: 0,
+                next: 0
+            }),
+        );
+        assert_eq!(reader.next_request().map(|r| r.offset), Some(0));
+    }
+
+    #[test]
+    fn reassembly_past_total() {
+        let mut reader = RingbufReader::new(0);
+        let success = |total, next| RingbufResponseV0::Success { total, next };
+        let bad_total = |len, total| RingbufError::BadTotal {
+            offset: 0,
+            len,
+            total,
+        };
+
+        // The chunk runs past the end, with or without a cursor.
+        assert_eq!(
+            reader.push(success(2, Some(3)), &[1, 2, 3]),
+            Err(bad_total(3, 2)),
+        );
+        assert_eq!(
+            reader.push(success(2, None), &[1, 2, 3]),
+            Err(bad_total(3, 2)),
+        );
+        // The cursor points past the end.
+        assert_eq!(
+            reader.push(success(3, Some(4)), &[1, 2, 3]),
+            Err(RingbufError::BadCursor {
+                offset: 0,
+                len: 3,
+                next: 4
+            }),
+        );
+        // The last chunk stops short of the end.
+        assert_eq!(
+            reader.push(success(5, None), &[1, 2, 3]),
+            Err(bad_total(3, 5)),
+        );
+        assert_eq!(reader.next_request().map(|r| r.offset), Some(0));
+        assert_eq!(reader.total(), None);
+    }
+}
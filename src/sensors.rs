@@ -0,0 +1,113 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sensor readings carried in response trailers.
+//!
+//! These are `hubpack`-encoded records, laid out as described in `records`.
+//! Like the top-level protocol enums, the order and presence of the variants
+//! of the enums here _is_ the protocol definition: add variants only at the
+//! end. A client that doesn't know a variant will fail to decode the records
+//! that use it, but can still decode the others.
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+/// One temperature reading, as found in the trailer of
+/// `TemperaturesResponseV0::Success`.
+///
+/// Each record occupies `TemperatureRecord::MAX_SIZE` (10) bytes:
+///
+/// | Offset | Size | Field                                         |
+/// |--------|------|-----------------------------------------------|
+/// | 0      | 4    | `sensor`, little-endian `u32`                 |
+/// | 4      | 1    | `kind`, as a `TemperatureSensorKind` index    |
+/// | 5      | 4    | `value`, little-endian `i32`                  |
+/// | 9      | 1    | `status`, as a `SensorStatus` index           |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct TemperatureRecord {
+    /// The SP's identifier for the sensor. This is stable for a given board
+    /// revision and firmware image, but not across them.
+    pub sensor: u32,
+    /// What kind of device the sensor is measuring.
+    pub kind: TemperatureSensorKind,
+    /// Temperature in thousandths of a degree Celsius. Only meaningful if
+    /// `status` is `SensorStatus::Ok`.
+    pub value: i32,
+    /// Whether `value` holds a valid reading.
+    pub status: SensorStatus,
+}
+
+/// Kinds of device a temperature sensor can be measuring.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TemperatureSensorKind {
+    /// The host CPU.
+    Cpu,
+    /// A DIMM.
+    Dimm,
+    /// A sensor measuring the air or board near it, rather than a particular
+    /// device.
+    Ambient,
+    /// The network interface controller.
+    Nic,
+    /// An M.2 or U.2 storage device.
+    Storage,
+    /// A voltage regulator or other power-conversion device.
+    PowerConversion,
+    /// Anything not covered by the other variants.
+    Other,
+}
+
+/// Status of a single sensor reading.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum SensorStatus {
+    /// The reading is valid.
+    Ok,
+    /// The device being measured isn't fitted, such as an empty DIMM slot.
+    NotPresent,
+    /// The device is fitted but not powered in the current power state.
+    Unpowered,
+    /// The sensor couldn't be read.
+    DeviceError,
+}
+
+/// One power rail's telemetry, as found in the trailer of
+/// `PowerRailsResponseV0::Success`.
+///
+/// Each record occupies `PowerRailRecord::MAX_SIZE` (13) bytes:
+///
+/// | Offset | Size | Field                                 |
+/// |--------|------|---------------------------------------|
+/// | 0      | 4    | `rail`, little-endian `u32`           |
+/// | 4      | 4    | `voltage`, little-endian `i32`        |
+/// | 8      | 4    | `current`, little-endian `i32`        |
+/// | 12     | 1    | `status`, as a `SensorStatus` index   |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct PowerRailRecord {
+    /// The SP's identifier for the rail. As with `TemperatureRecord::sensor`,
+    /// this is stable for a given board revision and firmware image, but not
+    /// across them; mapping it to a rail name is up to the host.
+    pub rail: u32,
+    /// Output voltage in millivolts. Only meaningful if `status` is
+    /// `SensorStatus::Ok`.
+    pub voltage: i32,
+    /// Output current in milliamps. Only meaningful if `status` is
+    /// `SensorStatus::Ok`.
+    pub current: i32,
+    /// Whether `voltage` and `current` hold valid readings. A rail whose
+    /// regulator can't be read is reported here as `SensorStatus::DeviceError`
+    /// while the other rails are still reported normally.
+    pub status: SensorStatus,
+}
@@ -0,0 +1,516 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Agent-side request dispatch.
+//!
+//! The agent implements `InspectorHandler` to collect the data for each query,
+//! and feeds every received packet through `dispatch`, which takes care of
+//! decoding the request and encoding the matching response.
+
+use crate::{
+    decode_request, encode_error_response, encode_error_response_v1,
+    encode_response, encode_response_v1, CapabilitiesResponseV0,
+    CapabilitiesV0, ErrorResponse, FpgaStatusResponseV0, FrameError,
+    HostPowerStateResponseV0, IdentityResponseV0, PostCodesResponseV0,
+    PowerRailsResponseV0, QueryV0, Response, RingbufRequestV0,
+    RingbufResponseV0, SequencerRegistersResponseV0, TasksResponseV0,
+    TemperaturesResponseV0, HOST_POWER_RESP_V0_TRAILER,
+    POST_CODES_RESP_V0_TRAILER, POWER_RAILS_RESP_V0_TRAILER,
+    RINGBUF_RESP_V0_TRAILER, SEQ_REG_RESP_V0_TRAILER, TASKS_RESP_V0_TRAILER,
+    TEMPS_RESP_V0_TRAILER,
+};
+
+/// Operations an agent must provide, one per `QueryV0` variant.
+///
+/// Methods that can produce a trailer are handed a buffer sized to the limit
+/// for their response, and return the response along with the number of
+/// trailer bytes they wrote into it.
+pub trait InspectorHandler {
+    /// Handles `QueryV0::SequencerRegisters`. On `Success`, the register dump
+    /// should be written into `trailer`.
+    fn sequencer_registers(
+        &mut self,
+        trailer: &mut [u8; SEQ_REG_RESP_V0_TRAILER],
+    ) -> (SequencerRegistersResponseV0, usize);
+
+    /// Handles `QueryV0::Capabilities`. The default reports everything defined
+    /// in this version of the crate, which is correct for agents that pass all
+    /// requests through `dispatch`.
+    fn capabilities(&mut self) -> CapabilitiesResponseV0 {
+        CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT)
+    }
+
+    /// Handles `QueryV0::Temperatures`. On `Success`, the readings should be
+    /// written into `trailer` with `records::encode_records`.
+    fn temperatures(
+        &mut self,
+        trailer: &mut [u8; TEMPS_RESP_V0_TRAILER],
+    ) -> (TemperaturesResponseV0, usize);
+
+    /// Handles `QueryV0::PowerRails`. On `Success`, the telemetry should be
+    /// written into `trailer` with `records::encode_records`, including a
+    /// record for each rail that couldn't be read.
+    fn power_rails(
+        &mut self,
+        trailer: &mut [u8; POWER_RAILS_RESP_V0_TRAILER],
+    ) -> (PowerRailsResponseV0, usize);
+
+    /// Handles `QueryV0::HostPowerState`. On `Success`, the transition history
+    /// should be written into `trailer` with `records::encode_records`, oldest
+    /// first.
+    fn host_power_state(
+        &mut self,
+        trailer: &mut [u8; HOST_POWER_RESP_V0_TRAILER],
+    ) -> (HostPowerStateResponseV0, usize);
+
+    /// Handles `QueryV0::Tasks`. The task table should be written into
+    /// `trailer` with `records::encode_records`, in task index order.
+    fn tasks(
+        &mut self,
+        trailer: &mut [u8; TASKS_RESP_V0_TRAILER],
+    ) -> (TasksResponseV0, usize);
+
+    /// Handles `QueryV0::Identity`.
+    fn identity(&mut self) -> IdentityResponseV0;
+
+    /// Handles `QueryV0::FpgaStatus`.
+    fn fpga_status(&mut self) -> FpgaStatusResponseV0;
+
+    /// Handles `QueryV0::PostCodes`. On `Success`, the codes should be written
+    /// into `trailer` with `records::encode_prefixed_records`, oldest first.
+    fn post_codes(
+        &mut self,
+        trailer: &mut [u8; POST_CODES_RESP_V0_TRAILER],
+    ) -> (PostCodesResponseV0, usize);
+
+    /// Handles `QueryV0::Ringbuf`. On `Success`, up to
+    /// `RINGBUF_RESP_V0_TRAILER` bytes of the ringbuf starting at
+    /// `request.offset` should be copied into `trailer`.
+    fn ringbuf(
+        &mut self,
+        request: RingbufRequestV0,
+        trailer: &mut [u8; RINGBUF_RESP_V0_TRAILER],
+    ) -> (RingbufResponseV0, usize);
+}
+
+/// Decodes the request in `packet_in`, invokes the matching method on
+/// `handler`, and encodes the response into `packet_out`, returning its
+/// length. `packet_out` should be at least `ANY_RESPONSE_V1_MAX_SIZE` bytes.
+///
+/// Requests that can't be decoded are answered with an `ErrorResponse`, so
+/// every packet received gets a reply. Responses to `Request::V1` carry the
+/// request's ID. This only fails if the response can't be encoded, because
+/// `packet_out` is too small or the handler reported an oversized trailer.
+pub fn dispatch(
+    packet_in: &[u8],
+    packet_out: &mut [u8],
+    handler: &mut impl InspectorHandler,
+) -> Result<usize, FrameError> {
+    let (request, request_trailer) = match decode_request(packet_in) {
+        Ok(decoded) => decoded,
+        Err(_) => {
+            return match classify(packet_in) {
+                (Some(id), error) => {
+                    encode_error_response_v1(id, error, packet_out)
+                }
+                (None, error) => encode_error_response(error, packet_out),
+            };
+        }
+    };
+    let id = request.id();
+
+    match request.query() {
+        QueryV0::SequencerRegisters => {
+            let mut trailer = [0; SEQ_REG_RESP_V0_TRAILER];
+            let (response, len) = handler.sequencer_registers(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::Capabilities => {
+            respond(id, &handler.capabilities(), &[], 0, packet_out)
+        }
+        QueryV0::Temperatures => {
+            let mut trailer = [0; TEMPS_RESP_V0_TRAILER];
+            let (response, len) = handler.temperatures(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::PowerRails => {
+            let mut trailer = [0; POWER_RAILS_RESP_V0_TRAILER];
+            let (response, len) = handler.power_rails(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::HostPowerState => {
+            let mut trailer = [0; HOST_POWER_RESP_V0_TRAILER];
+            let (response, len) = handler.host_power_state(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::Tasks => {
+            let mut trailer = [0; TASKS_RESP_V0_TRAILER];
+            let (response, len) = handler.tasks(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::Identity => {
+            respond(id, &handler.identity(), &[], 0, packet_out)
+        }
+        QueryV0::FpgaStatus => {
+            respond(id, &handler.fpga_status(), &[], 0, packet_out)
+        }
+        QueryV0::PostCodes => {
+            let mut trailer = [0; POST_CODES_RESP_V0_TRAILER];
+            let (response, len) = handler.post_codes(&mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+        QueryV0::Ringbuf => {
+            let request = match hubpack::deserialize(request_trailer) {
+                Ok((request, [])) => request,
+                _ => {
+                    let error = ErrorResponse::MalformedRequest;
+                    return match id {
+                        Some(id) => {
+                            encode_error_response_v1(id, error, packet_out)
+                        }
+                        None => encode_error_response(error, packet_out),
+                    };
+                }
+            };
+            let mut trailer = [0; RINGBUF_RESP_V0_TRAILER];
+            let (response, len) = handler.ringbuf(request, &mut trailer);
+            respond(id, &response, &trailer, len, packet_out)
+        }
+    }
+}
+
+/// Encodes `response` with the first `len` bytes of `trailer`, behind a
+/// `ResponseHeaderV1` if the request carried an ID.
+fn respond<T: Response>(
+    id: Option<u32>,
+    response: &T,
+    trailer: &[u8],
+    len: usize,
+    out: &mut [u8],
+) -> Result<usize, FrameError> {
+    let trailer = trailer.get(..len).ok_or(FrameError::TrailerTooLong {
+        max: trailer.len(),
+        actual: len,
+    })?;
+    match id {
+        Some(id) => encode_response_v1(id, response, trailer, out),
+        None => encode_response(response, trailer, out),
+    }
+}
+
+/// Works out why `packet` failed to decode as a `Request`, and recovers its
+/// ID if it has one.
+fn classify(packet: &[u8]) -> (Option<u32>, ErrorResponse) {
+    match packet {
+        [0, rest @ ..] => (None, classify_query(rest)),
+        [1, rest @ ..] => match hubpack::deserialize::<u32>(rest) {
+            Ok((id, rest)) => (Some(id), classify_query(rest)),
+            Err(_) => (None, ErrorResponse::MalformedRequest),
+        },
+        [] => (None, ErrorResponse::MalformedRequest),
+        [_, ..] => (None, ErrorResponse::UnsupportedVersion),
+    }
+}
+
+/// Works out why the query part of a request with a known version failed to
+/// decode: either the query index is bad or something else is.
+fn classify_query(rest: &[u8]) -> ErrorResponse {
+    match rest {
+        [query, ..] if hubpack::deserialize::<QueryV0>(&[*query]).is_err() => {
+            ErrorResponse::UnknownQuery
+        }
+        _ => ErrorResponse::MalformedRequest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        IdentityV0, Request, ANY_RESPONSE_V1_MAX_SIZE, REQUEST_MAX_SIZE,
+    };
+
+    struct FakeAgent {
+        response: SequencerRegistersResponseV0,
+        trailer_len: usize,
+    }
+
+    impl InspectorHandler for FakeAgent {
+        fn sequencer_registers(
+            &mut self,
+            trailer: &mut [u8; SEQ_REG_RESP_V0_TRAILER],
+        ) -> (SequencerRegistersResponseV0, usize) {
+            trailer.fill(0x5a);
+            (self.response, self.trailer_len)
+        }
+
+        fn temperatures(
+            &mut self,
+            _trailer: &mut [u8; TEMPS_RESP_V0_TRAILER],
+        ) -> (TemperaturesResponseV0, usize) {
+            (TemperaturesResponseV0::SensorTaskDead, 0)
+        }
+
+        fn power_rails(
+            &mut self,
+            _trailer: &mut [u8; POWER_RAILS_RESP_V0_TRAILER],
+        ) -> (PowerRailsResponseV0, usize) {
+            (PowerRailsResponseV0::PowerTaskDead, 0)
+        }
+
+        fn host_power_state(
+            &mut self,
+            _trailer: &mut [u8; HOST_POWER_RESP_V0_TRAILER],
+        ) -> (HostPowerStateResponseV0, usize) {
+            (HostPowerStateResponseV0::SequencerTaskDead, 0)
+        }
+
+        fn tasks(
+            &mut self,
+            _trailer: &mut [u8; TASKS_RESP_V0_TRAILER],
+        ) -> (TasksResponseV0, usize) {
+            (TasksResponseV0::Success { total: 0 }, 0)
+        }
+
+        fn identity(&mut self) -> IdentityResponseV0 {
+            IdentityResponseV0::Success(IdentityV0 {
+                model: [0; 16],
+                revision: 0,
+                serial: [0; 16],
+                image_version: 0,
+                git_commit: [0; 20],
+                fpga_bitstream: 0,
+            })
+        }
+
+        fn fpga_status(&mut self) -> FpgaStatusResponseV0 {
+            FpgaStatusResponseV0::SequencerTaskDead
+        }
+
+        fn post_codes(
+            &mut self,
+            _trailer: &mut [u8; POST_CODES_RESP_V0_TRAILER],
+        ) -> (PostCodesResponseV0, usize) {
+            (PostCodesResponseV0::NoHostPower, 0)
+        }
+
+        fn ringbuf(
+            &mut self,
+            request: RingbufRequestV0,
+            trailer: &mut [u8; RINGBUF_RESP_V0_TRAILER],
+        ) -> (RingbufResponseV0, usize) {
+            // A 4-byte ringbuf whose bytes are their own offsets, sent one
+            // byte at a time.
+            if request.ringbuf != 0 {
+                return (RingbufResponseV0::NoSuchRingbuf, 0);
+            }
+            let Some(next) = request.offset.checked_add(1).filter(|&n| n <= 4)
+            else {
+                return (RingbufResponseV0::BadOffset, 0);
+            };
+            trailer[0] = request.offset as u8;
+            let next = (next < 4).then_some(next);
+            (RingbufResponseV0::Success { total: 4, next }, 1)
+        }
+    }
+
+    fn request(request: Request) -> ([u8; REQUEST_MAX_SIZE], usize) {
+        let mut buf = [0; REQUEST_MAX_SIZE];
+        let len = crate::encode_request(&request, &[], &mut buf).unwrap();
+        (buf, len)
+    }
+
+    #[test]
+    fn dispatch_sequencer_registers() {
+        let (packet_in, len) =
+            request(Request::V0(QueryV0::SequencerRegisters));
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: SEQ_REG_RESP_V0_TRAILER,
+        };
+        let n =
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent).unwrap();
+        assert_eq!(
+            crate::decode_response(&packet_out[..n]),
+            Ok((
+                SequencerRegistersResponseV0::Success,
+                &[0x5a; SEQ_REG_RESP_V0_TRAILER][..]
+            )),
+        );
+
+        // A failure response must not carry the trailer the handler left
+        // behind.
+        agent.response = SequencerRegistersResponseV0::SequencerTaskDead;
+        assert_eq!(
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent),
+            Err(FrameError::TrailerTooLong {
+                max: 0,
+                actual: SEQ_REG_RESP_V0_TRAILER,
+            }),
+        );
+        agent.trailer_len = 0;
+        assert_eq!(
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent),
+            Ok(1),
+        );
+    }
+
+    #[test]
+    fn dispatch_bad_requests() {
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: 0,
+        };
+        let mut check = |packet: &[u8], expected| {
+            let n = dispatch(packet, &mut packet_out, &mut agent).unwrap();
+            assert_eq!(
+                crate::decode_response::<SequencerRegistersResponseV0>(
+                    &packet_out[..n]
+                ),
+                Err(FrameError::ErrorResponse(expected)),
+            );
+        };
+        check(&[0xee, 0], ErrorResponse::UnsupportedVersion);
+        check(&[0, 0xee], ErrorResponse::UnknownQuery);
+        check(&[], ErrorResponse::MalformedRequest);
+        check(&[0, 0, 1], ErrorResponse::MalformedRequest);
+        check(&[1, 0, 0], ErrorResponse::MalformedRequest);
+    }
+
+    #[test]
+    fn dispatch_answers_every_short_packet() {
+        // The fuzz targets cover this more thoroughly, but every packet of up
+        // to two bytes is cheap enough to check exhaustively.
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: SEQ_REG_RESP_V0_TRAILER,
+        };
+        dispatch(&[], &mut packet_out, &mut agent).unwrap();
+        for a in 0..=u8::MAX {
+            dispatch(&[a], &mut packet_out, &mut agent).unwrap();
+            for b in 0..=u8::MAX {
+                dispatch(&[a, b], &mut packet_out, &mut agent).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_v1_echoes_id() {
+        let (packet_in, len) = request(Request::V1 {
+            id: 1234,
+            query: QueryV0::SequencerRegisters,
+        });
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::SequencerReadRegsFailed,
+            trailer_len: 0,
+        };
+        let n =
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent).unwrap();
+        assert_eq!(
+            crate::decode_response_v1(1234, &packet_out[..n]),
+            Ok((
+                SequencerRegistersResponseV0::SequencerReadRegsFailed,
+                &[][..]
+            )),
+        );
+
+        // Errors echo the ID too, when it can be recovered.
+        let mut bad = packet_in;
+        bad[len - 1] = 0xee;
+        let n = dispatch(&bad[..len], &mut packet_out, &mut agent).unwrap();
+        assert_eq!(
+            crate::decode_response_v1::<SequencerRegistersResponseV0>(
+                1235,
+                &packet_out[..n]
+            ),
+            Err(FrameError::WrongId {
+                expected: 1235,
+                actual: 1234
+            }),
+        );
+        assert_eq!(
+            crate::decode_response_v1::<SequencerRegistersResponseV0>(
+                1234,
+                &packet_out[..n]
+            ),
+            Err(FrameError::ErrorResponse(ErrorResponse::UnknownQuery)),
+        );
+    }
+
+    #[test]
+    fn dispatch_capabilities() {
+        let (packet_in, len) = request(Request::V0(QueryV0::Capabilities));
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: 0,
+        };
+        let n =
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent).unwrap();
+        assert_eq!(
+            crate::decode_response(&packet_out[..n]),
+            Ok((
+                CapabilitiesResponseV0::Success(CapabilitiesV0::CURRENT),
+                &[][..]
+            )),
+        );
+    }
+
+    #[test]
+    fn dispatch_temperatures() {
+        let (packet_in, len) = request(Request::V0(QueryV0::Temperatures));
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: 0,
+        };
+        let n =
+            dispatch(&packet_in[..len], &mut packet_out, &mut agent).unwrap();
+        assert_eq!(
+            crate::decode_response(&packet_out[..n]),
+            Ok((TemperaturesResponseV0::SensorTaskDead, &[][..])),
+        );
+    }
+
+    #[test]
+    fn dispatch_ringbuf() {
+        let mut agent = FakeAgent {
+            response: SequencerRegistersResponseV0::Success,
+            trailer_len: 0,
+        };
+        let mut packet_in = [0; REQUEST_MAX_SIZE];
+        let mut packet_out = [0; ANY_RESPONSE_V1_MAX_SIZE];
+        let mut reader = crate::ringbuf::RingbufReader::new(0);
+        while let Some(request) = reader.next_request() {
+            let mut trailer = [0; crate::RINGBUF_REQ_V0_TRAILER];
+            let len = hubpack::serialize(&mut trailer, &request).unwrap();
+            let len = crate::encode_request(
+                &Request::V0(QueryV0::Ringbuf),
+                &trailer[..len],
+                &mut packet_in,
+            )
+            .unwrap();
+            let n = dispatch(&packet_in[..len], &mut packet_out, &mut agent)
+                .unwrap();
+            let (response, chunk) =
+                crate::decode_response(&packet_out[..n]).unwrap();
+            reader.push(response, chunk).unwrap();
+        }
+        assert_eq!(reader.into_data(), [0, 1, 2, 3]);
+
+        // The query can't be answered without its trailer, or with a short
+        // one.
+        for packet_in in [&[0, 9][..], &[0, 9, 0, 0, 0]] {
+            let n = dispatch(packet_in, &mut packet_out, &mut agent).unwrap();
+            assert_eq!(
+                crate::decode_response::<RingbufResponseV0>(&packet_out[..n]),
+                Err(FrameError::ErrorResponse(ErrorResponse::MalformedRequest)),
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Rack-wide sweeps, collecting sequencer registers from many SPs at once so
+//! that they can be compared. Requires the `async` feature.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use futures::StreamExt;
+
+use crate::async_client::{AsyncInspectorClient, Reply};
+use crate::query;
+use crate::SequencerRegistersResponseV0;
+
+/// The result of `sweep_sequencer_registers`.
+#[derive(Debug)]
+pub struct SequencerSweep {
+    /// What each SP replied, in the order the SPs were given, with duplicates
+    /// removed. On `Success`, the trailer is the register dump.
+    pub results: Vec<(SocketAddr, Reply<query::SequencerRegisters>)>,
+}
+
+/// A register that doesn't hold the same value on every SP in a sweep.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegisterDiff {
+    /// Offset of the register in the dump.
+    pub offset: usize,
+    /// The value held by the most SPs. Ties go to the lowest value.
+    pub common: u8,
+    /// Every SP holding some other value, and that value.
+    pub outliers: Vec<(SocketAddr, u8)>,
+}
+
+/// Issues `QueryV0::SequencerRegisters` to every SP in `sps` at once, and
+/// waits until each has answered or run out of retries.
+pub async fn sweep_sequencer_registers(
+    client: &AsyncInspectorClient,
+    sps: &[SocketAddr],
+) -> SequencerSweep {
+    let mut order = HashMap::new();
+    for &sp in sps {
+        let next = order.len();
+        order.entry(sp).or_insert(next);
+    }
+    let mut results: Vec<_> = client
+        .call_many::<query::SequencerRegisters>(order.keys().copied())
+        .collect()
+        .await;
+    results.sort_by_key(|(sp, _)| order[sp]);
+    SequencerSweep { results }
+}
+
+impl SequencerSweep {
+    /// Returns the register dump from each SP that answered `Success`.
+    pub fn dumps(&self) -> impl Iterator<Item = (SocketAddr, &[u8])> {
+        self.results.iter().filter_map(|(sp, result)| match result {
+            Ok((SequencerRegistersResponseV0::Success, dump)) => {
+                Some((*sp, &dump[..]))
+            }
+            _ => None,
+        })
+    }
+
+    /// Compares the register dumps byte by byte, returning every register
+    /// that differs between SPs, in offset order. Only offsets present in
+    /// every dump are compared.
+    pub fn register_diffs(&self) -> Vec<RegisterDiff> {
+        let dumps: Vec<_> = self.dumps().collect();
+        let len = dumps.iter().map(|(_, dump)| dump.len()).min().unwrap_or(0);
+        let mut diffs = Vec::new();
+        for offset in 0..len {
+            let mut counts = [0usize; 256];
+            for (_, dump) in &dumps {
+                counts[usize::from(dump[offset])] += 1;
+            }
+            // `max_by_key` keeps the last maximum, so search from the top to
+            // break ties toward the lowest value.
+            let common = (0..=255u8)
+                .rev()
+                .max_by_key(|&value| counts[usize::from(value)])
+                .unwrap();
+            let outliers: Vec<_> = dumps
+                .iter()
+                .filter(|(_, dump)| dump[offset] != common)
+                .map(|(sp, dump)| (*sp, dump[offset]))
+                .collect();
+            if !outliers.is_empty() {
+                diffs.push(RegisterDiff {
+                    offset,
+                    common,
+                    outliers,
+                });
+            }
+        }
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ClientError, RequestVersion};
+    use crate::mock::{MockAgent, MockReply};
+    use crate::{QueryV0, SEQ_REG_RESP_V0_TRAILER};
+    use std::time::Duration;
+
+    fn sp(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn success(dump: &[u8]) -> Reply<query::SequencerRegisters> {
+        Ok((SequencerRegistersResponseV0::Success, dump.to_vec()))
+    }
+
+    #[test]
+    fn diffs_against_majority() {
+        let sweep = SequencerSweep {
+            results: vec![
+                (sp(1), success(&[1, 0x10, 0xaa])),
+                (sp(2), success(&[1, 0x20, 0xbb, 0xff])),
+                (sp(3), success(&[1, 0x10, 0xcc])),
+                (
+                    sp(4),
+                    Ok((
+                        SequencerRegistersResponseV0::SequencerTaskDead,
+                        vec![],
+                    )),
+                ),
+                (sp(5), Err(ClientError::Timeout { attempts: 1 })),
+            ],
+        };
+        assert_eq!(
+            sweep.register_diffs(),
+            [
+                RegisterDiff {
+                    offset: 1,
+                    common: 0x10,
+                    outliers: vec![(sp(2), 0x20)],
+                },
+                RegisterDiff {
+                    offset: 2,
+                    common: 0xaa,
+                    outliers: vec![(sp(2), 0xbb), (sp(3), 0xcc)],
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn sweep_mock_agents() {
+        let agents: Vec<MockAgent> =
+            (0..3).map(|_| MockAgent::start().unwrap()).collect();
+        let mut dump = [0; SEQ_REG_RESP_V0_TRAILER];
+        dump[0x0b] = 0x80;
+        agents[0].push(
+            QueryV0::SequencerRegisters,
+            MockReply::SequencerRegisters(
+                SequencerRegistersResponseV0::Success,
+                dump.to_vec(),
+            ),
+        );
+        agents[1].push(
+            QueryV0::SequencerRegisters,
+            MockReply::SequencerRegisters(
+                SequencerRegistersResponseV0::SequencerReadRegsFailed,
+                vec![],
+            ),
+        );
+        agents[2].push(QueryV0::SequencerRegisters, MockReply::Drop);
+
+        // The last agent is listed twice, but only asked once.
+        let mut sps: Vec<_> = agents.iter().map(MockAgent::addr).collect();
+        sps.insert(0, sps[2]);
+        let sweep = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let client = AsyncInspectorClient::bind("127.0.0.1:0")
+                    .await
+                    .unwrap()
+                    .with_timeout(Duration::from_millis(100))
+                    .with_retries(0)
+                    .with_version(RequestVersion::V1);
+                sweep_sequencer_registers(&client, &sps).await
+            });
+
+        let results: Vec<_> = sweep
+            .results
+            .iter()
+            .map(|(sp, result)| (*sp, result.as_ref().map(|(r, _)| *r).ok()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                (sps[0], None),
+                (sps[1], Some(SequencerRegistersResponseV0::Success)),
+                (
+                    sps[2],
+                    Some(SequencerRegistersResponseV0::SequencerReadRegsFailed)
+                ),
+            ],
+        );
+        assert_eq!(agents[2].received().len(), 1);
+        assert!(sweep.register_diffs().is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hubris task status, as reported in response to `QueryV0::Tasks`.
+//!
+//! Tasks are identified by their index in the SP image. Names aren't sent;
+//! look them up in the image's archive, as Humility does.
+//!
+//! As with the top-level protocol enums, the order and presence of the
+//! variants of the enums here _is_ the protocol definition: add variants only
+//! at the end.
+
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+
+/// Status of one task, as found in the trailer of `TasksResponseV0::Success`.
+///
+/// Each record occupies `TaskRecord::MAX_SIZE` (9) bytes:
+///
+/// | Offset | Size | Field                                          |
+/// |--------|------|------------------------------------------------|
+/// | 0      | 2    | `index`, little-endian `u16`                   |
+/// | 2      | 1    | `generation`                                   |
+/// | 3      | 4    | `restarts`, little-endian `u32`                |
+/// | 7      | 1    | `state`, as a `TaskState` index                |
+/// | 8      | 1    | `TaskFault` index if faulted, otherwise zero   |
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub struct TaskRecord {
+    /// The task's index in the image.
+    pub index: u16,
+    /// The task's current generation, which the kernel bumps each time the
+    /// task is restarted. It wraps, so use `restarts` for counting.
+    pub generation: u8,
+    /// How many times the supervisor has restarted the task since the SP
+    /// booted. A task whose count keeps climbing is crash-looping.
+    pub restarts: u32,
+    /// What the task is doing now.
+    pub state: TaskState,
+}
+
+/// Scheduling state of a task.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TaskState {
+    /// The task is stopped and won't be scheduled, such as a task that isn't
+    /// started at boot.
+    Stopped,
+    /// The task is ready to run or running.
+    Runnable,
+    /// The task is blocked sending a message, waiting for a reply, or waiting
+    /// for a message or notification.
+    Blocked,
+    /// The task has faulted and is waiting for the supervisor to restart it.
+    Faulted(TaskFault),
+}
+
+/// Kinds of task fault, following the Hubris kernel's `FaultInfo`.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+#[cfg_attr(any(test, feature = "proptest"), derive(proptest_derive::Arbitrary))]
+pub enum TaskFault {
+    /// The task touched memory it doesn't have access to.
+    MemoryAccess,
+    /// The task overflowed its stack.
+    StackOverflow,
+    /// A bus error, either precise or imprecise.
+    BusError,
+    /// The task divided by zero.
+    DivideByZero,
+    /// The task jumped to memory that isn't executable.
+    IllegalText,
+    /// The task executed an undefined instruction.
+    IllegalInstruction,
+    /// Some other processor fault.
+    InvalidOperation,
+    /// The task misused a system call.
+    SyscallUsage,
+    /// The task panicked.
+    Panic,
+    /// Another task, usually the supervisor or a debugger, injected a fault.
+    Injected,
+    /// A server faulted the task for sending it a bad message.
+    FromServer,
+}
@@ -0,0 +1,246 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Guard against reordering or removing protocol enum variants.
+//!
+//! `testdata/variants.txt` records the wire index of every variant of every
+//! protocol enum, and is append-only. The test here fails if any recorded
+//! variant is missing or has moved, and if any variant hasn't been recorded.
+//! When adding a variant at the end of an enum, append a line for it to the
+//! manifest; never edit existing lines.
+
+use std::collections::BTreeMap;
+
+use crate::power::*;
+use crate::sensors::*;
+use crate::tasks::*;
+use crate::*;
+
+const MANIFEST: &str = "testdata/variants.txt";
+
+/// Lists every variant of a protocol enum along with a sample value, and
+/// returns `(enum, variant, index)` for each.
+///
+/// The expansion includes an exhaustive match on the listed variants, so
+/// adding a variant to an enum without listing it here fails to compile.
+macro_rules! variants {
+    ($ty:ident { $($variant:ident $(= $sample:expr)?),* $(,)? }) => {{
+        #[allow(dead_code)]
+        fn exhaustive(v: $ty) {
+            match v {
+                $($ty::$variant { .. } => (),)*
+            }
+        }
+        vec![$(
+            (
+                stringify!($ty),
+                stringify!($variant),
+                index(&variants!(@sample $ty $variant $(= $sample)?)),
+            ),
+        )*]
+    }};
+    (@sample $ty:ident $variant:ident = $sample:expr) => { $sample };
+    (@sample $ty:ident $variant:ident) => { $ty::$variant };
+}
+
+/// Returns the wire index of `value`'s variant: the first encoded byte.
+fn index<T: serde::Serialize + hubpack::SerializedSize>(value: &T) -> u8 {
+    let mut buf = vec![0; T::MAX_SIZE];
+    hubpack::serialize(&mut buf, value).unwrap();
+    buf[0]
+}
+
+fn all_variants() -> Vec<(&'static str, &'static str, u8)> {
+    let caps = CapabilitiesV0::CURRENT;
+    let query = QueryV0::SequencerRegisters;
+    [
+        variants!(Request {
+            V0 = Request::V0(query),
+            V1 = Request::V1 { id: 0, query },
+        }),
+        variants!(QueryV0 {
+            SequencerRegisters,
+            Capabilities,
+            Temperatures,
+            PowerRails,
+            HostPowerState,
+            Tasks,
+            Identity,
+            FpgaStatus,
+            PostCodes,
+            Ringbuf,
+        }),
+        variants!(SequencerRegistersResponseV0 {
+            Success,
+            SequencerTaskDead,
+            SequencerReadRegsFailed,
+        }),
+        variants!(CapabilitiesResponseV0 {
+            Success = CapabilitiesResponseV0::Success(caps),
+        }),
+        variants!(TemperaturesResponseV0 {
+            Success,
+            SensorTaskDead,
+        }),
+        variants!(TemperatureSensorKind {
+            Cpu,
+            Dimm,
+            Ambient,
+            Nic,
+            Storage,
+            PowerConversion,
+            Other,
+        }),
+        variants!(SensorStatus {
+            Ok,
+            NotPresent,
+            Unpowered,
+            DeviceError,
+        }),
+        variants!(PowerRailsResponseV0 {
+            Success,
+            PowerTaskDead,
+        }),
+        variants!(HostPowerStateResponseV0 {
+            Success = HostPowerStateResponseV0::Success {
+                state: HostPowerState::A0,
+                now: 0,
+            },
+            SequencerTaskDead,
+        }),
+        variants!(HostPowerState {
+            A2,
+            A2PlusFans,
+            A1,
+            A0,
+            A0PlusHP,
+            A0Thermtrip,
+            A0Reset,
+        }),
+        variants!(TransitionCause {
+            SpBoot,
+            ControlPlane,
+            HostRequest,
+            Thermtrip,
+            SequencerFault,
+            Other,
+        }),
+        variants!(TasksResponseV0 {
+            Success = TasksResponseV0::Success { total: 0 },
+        }),
+        variants!(TaskState {
+            Stopped,
+            Runnable,
+            Blocked,
+            Faulted = TaskState::Faulted(TaskFault::Panic),
+        }),
+        variants!(TaskFault {
+            MemoryAccess,
+            StackOverflow,
+            BusError,
+            DivideByZero,
+            IllegalText,
+            IllegalInstruction,
+            InvalidOperation,
+            SyscallUsage,
+            Panic,
+            Injected,
+            FromServer,
+        }),
+        variants!(IdentityResponseV0 {
+            Success = IdentityResponseV0::Success(IdentityV0 {
+                model: [0; 16],
+                revision: 0,
+                serial: [0; 16],
+                image_version: 0,
+                git_commit: [0; 20],
+                fpga_bitstream: 0,
+            }),
+        }),
+        variants!(FpgaStatusResponseV0 {
+            Success = FpgaStatusResponseV0::Success(FpgaStatusV0 {
+                    state: FpgaConfigState::Configured,
+                    checksum: 0,
+                    expected_checksum: 0,
+                    error_code: 0,
+                }),
+            SequencerTaskDead,
+            FpgaStatusReadFailed,
+        }),
+        variants!(FpgaConfigState {
+            Unconfigured,
+            Configuring,
+            Configured,
+            LoadFailed,
+        }),
+        variants!(PostCodesResponseV0 {
+            Success = PostCodesResponseV0::Success { total: 0 },
+            NoHostPower,
+            BufferUnavailable,
+        }),
+        variants!(RingbufResponseV0 {
+            Success = RingbufResponseV0::Success {
+                total: 0,
+                next: None,
+            },
+            NoSuchRingbuf,
+            BadOffset,
+            ReadFailed,
+        }),
+        variants!(ErrorResponse {
+            UnsupportedVersion,
+            UnknownQuery,
+            MalformedRequest,
+        }),
+    ]
+    .concat()
+}
+
+#[test]
+fn variant_indices_match_manifest() {
+    let manifest = std::fs::read_to_string(MANIFEST).unwrap();
+    let mut recorded = BTreeMap::new();
+    for line in manifest.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [ty, index, variant] = fields[..] else {
+            panic!("bad line in {MANIFEST}: {line:?}");
+        };
+        let index: u8 = index.parse().unwrap();
+        let prev = recorded.insert((ty, variant), index);
+        assert!(prev.is_none(), "{ty}::{variant} recorded twice");
+    }
+
+    let mut problems = Vec::new();
+    let mut current = BTreeMap::new();
+    for (ty, variant, index) in all_variants() {
+        current.insert((ty, variant), index);
+        match recorded.get(&(ty, variant)) {
+            Some(&r) if r == index => (),
+            Some(&r) => problems.push(format!(
+                "{ty}::{variant} was recorded at index {r} but is now {index}"
+            )),
+            None => problems.push(format!(
+                "{ty}::{variant} is new; append `{ty} {index} {variant}` \
+                 to {MANIFEST}"
+            )),
+        }
+    }
+    for ((ty, variant), index) in &recorded {
+        if !current.contains_key(&(*ty, *variant)) {
+            problems.push(format!(
+                "{ty}::{variant} was recorded at index {index} but no longer \
+                 exists"
+            ));
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "protocol enum variants don't match {MANIFEST}:\n{}",
+        problems.join("\n"),
+    );
+}